@@ -0,0 +1,40 @@
+//! Benchmarks the processing of transactions.
+//!
+//! To compare the default SipHash hasher with `FxHasher`, save a baseline and compare against it:
+//! `cargo bench --bench processing -- --save-baseline siphash`, then
+//! `cargo bench --bench processing --features fxhash -- --baseline siphash`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::fmt::Write;
+use std::hint::black_box;
+
+/// Builds valid CSV input of deposits, withdrawals, disputes and resolves spread across clients.
+/// Every client gets one transaction per round; disputes and resolves target the deposit made by
+/// the same client five and six rounds earlier.
+fn transactions(client_count: u32, transaction_count: u32) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=transaction_count {
+        let client = (tx - 1) % client_count + 1;
+        let round = (tx - 1) / client_count;
+        let _ = match round % 8 {
+            4 => writeln!(input, "withdrawal,{},{},1.5", client, tx),
+            5 => writeln!(input, "dispute,{},{},", client, tx - 5 * client_count),
+            6 => writeln!(input, "resolve,{},{},", client, tx - 6 * client_count),
+            _ => writeln!(input, "deposit,{},{},10.0", client, tx),
+        };
+    }
+    input
+}
+
+fn processing(c: &mut Criterion) {
+    let input = transactions(1000, 100_000);
+    let mut group = c.benchmark_group("processing");
+    group.throughput(Throughput::Elements(100_000));
+    group.bench_function("process_transactions", |b| {
+        b.iter(|| rust_challenge_payments::process_transactions(black_box(input.as_bytes())))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, processing);
+criterion_main!(benches);
@@ -0,0 +1,679 @@
+//! The command line application.
+
+use crate::{
+    error::Error,
+    input::{
+        process_chained_transactions_into, process_namespaced_transactions,
+        process_sorted_transactions_into, Follower, RejectedRecordWriter, TrimMode,
+    },
+    output::{
+        merge_result, write_deltas, write_formatted_result, write_json_dir,
+        write_namespaced_deltas, write_namespaced_result, write_result, NamespacedClientId,
+        ResultFormat, ResultOptions,
+    },
+    processing::{sum_total_funds, ErrorVerbosity, ProcessingOptions},
+    state::{ProcessingState, ProcessingStats},
+    store::{select_transaction_store, TransactionStoreKind},
+    transaction::TransactionRecord,
+    types::{Clock, MoneyAmount, SystemClock, DECIMAL_PRECISION},
+};
+use clap::{Parser, Subcommand};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Write},
+};
+
+/// Highest exit status encoding a number of warnings; higher statuses have special meanings in
+/// shells.
+const MAX_WARNINGS_EXIT_CODE: u8 = 125;
+
+/// How often a followed transaction file is checked for new lines.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[clap(name = "Rust Payments Challenge")]
+#[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub(crate) struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Files containing the transactions to process. Several files are processed one after the
+    /// other as a single input. Transactions are read from stdin when no file is given.
+    pub(crate) transactions_filepaths: Vec<PathBuf>,
+
+    /// Read the transactions from stdin, and write the result to stdout once stdin is closed, as a
+    /// step of a pipeline. This is the default when no file is given.
+    #[clap(
+        long,
+        conflicts_with_all = ["transactions_filepaths", "follow", "namespace_by_file"]
+    )]
+    stream: bool,
+
+    /// Process each transaction file as a separate tenant: clients of different files are
+    /// isolated, and client IDs are prefixed with the file number in the result (`2:1` is client 1
+    /// of the second file). Transaction IDs stay global, but disputes can only target transactions
+    /// of the same file.
+    #[clap(
+        long,
+        requires = "transactions_filepaths",
+        conflicts_with_all = [
+            "follow",
+            "assume_sorted_by_client",
+            "opening_balances",
+            "resume_state",
+            "dump_state",
+        ]
+    )]
+    namespace_by_file: bool,
+
+    /// Echo every parsed transaction record to stderr before processing it.
+    #[clap(long)]
+    echo_input: bool,
+
+    /// Number of most recent deposits per client that cannot be withdrawn yet; a deposit matures
+    /// once this many newer deposits have been made by the same client.
+    #[clap(long, value_name = "COUNT", default_value_t = 0)]
+    hold_new_deposits: usize,
+
+    /// Keep reading transactions appended to the file, like `tail -f`, and periodically write a
+    /// snapshot of the clients' accounts. Each record must fit on a single line.
+    #[clap(long, requires = "transactions_filepaths")]
+    follow: bool,
+
+    /// Maximum amount each client can withdraw per UTC day. Withdrawals must then have a
+    /// timestamp.
+    #[clap(long, value_name = "AMOUNT")]
+    daily_withdrawal_limit: Option<Decimal>,
+
+    /// Add a column to the result with the sum of the amounts under an open dispute for each
+    /// client, which should be equal to its held funds minus its pending held deposits.
+    #[clap(long)]
+    verbose: bool,
+
+    /// Allow resolving a charged back transaction, which unlocks the client account. The funds
+    /// removed by the chargeback are not restored.
+    #[clap(long)]
+    allow_resolve_after_chargeback: bool,
+
+    /// Reject records that do not have every column of the header, even if the missing columns
+    /// would be empty, like the amount of a dispute.
+    #[clap(long)]
+    input_has_trailing_amount_always: bool,
+
+    /// Write the peak number of stored transactions and clients to stderr at the end of the run,
+    /// to help sizing the memory needed for large inputs. With `--namespace-by-file`, the peaks of
+    /// the files are summed, as every file stays in memory until the end of the run.
+    #[clap(long)]
+    report_peak_sizes: bool,
+
+    /// Count the transactions rejected because the client account is locked instead of logging
+    /// them, and write the count of each client to stderr at the end of the run.
+    #[clap(long)]
+    tally_locked_rejections: bool,
+
+    /// Assume that the transactions are grouped by client: each client is written to the result
+    /// and forgotten as soon as its transactions end, so that only one client is kept in memory.
+    /// Processing stops with an error if a client appears again later.
+    #[clap(long, conflicts_with_all = ["follow", "opening_balances", "delta_output", "resume_state"])]
+    assume_sorted_by_client: bool,
+
+    /// Do not write transaction errors, only their count at the end of the run.
+    #[clap(long, conflicts_with = "verbose_errors")]
+    quiet_errors: bool,
+
+    /// Include the record that caused each transaction error.
+    #[clap(long)]
+    verbose_errors: bool,
+
+    /// Check at the end of the run that the clients' total funds add up to the opening balances,
+    /// deposits, withdrawals, withdrawal disputes and deposit chargebacks, and fail otherwise.
+    #[clap(long, conflicts_with_all = ["follow", "assume_sorted_by_client"])]
+    audit_funds: bool,
+
+    /// Write the rows that have not been applied to a CSV file with the same columns as the input,
+    /// so that they can be submitted again once fixed.
+    #[clap(long, value_name = "PATH")]
+    dump_errors_csv: Option<PathBuf>,
+
+    /// Write a warning to stderr for every amount having more than 4 decimal places, whose
+    /// precision is lost when balances are rounded.
+    #[clap(long)]
+    warn_precision_loss: bool,
+
+    /// Credit interest on the available funds of every client at the end of the run, at the given
+    /// rate in basis points: 500 credits 5% of the available funds.
+    #[clap(long, value_name = "BPS", conflicts_with_all = ["follow", "assume_sorted_by_client"])]
+    interest_rate_bps: Option<u32>,
+
+    /// Maximum number of disputes each client can open; further disputes are rejected.
+    #[clap(long, value_name = "N")]
+    max_disputes_per_client: Option<usize>,
+
+    /// Also write the result entry of every client as JSON to a `<client>.json` file in the given
+    /// directory, which is created if needed. With `--namespace-by-file`, the clients of each file
+    /// are written to a subdirectory named after the file number.
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["follow", "assume_sorted_by_client"]
+    )]
+    json_dir: Option<PathBuf>,
+
+    /// Format of the result written to stdout.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = ResultFormat::Csv,
+        conflicts_with = "assume_sorted_by_client"
+    )]
+    format: ResultFormat,
+
+    /// Which parts of the transaction input have their surrounding spaces and tabs trimmed.
+    #[clap(long, value_enum, default_value_t = TrimMode::All)]
+    trim: TrimMode,
+
+    /// Where the transactions that can be targeted by disputes are stored. Storing them on disk
+    /// allows processing inputs whose transactions do not fit in memory; clients stay in memory.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = TransactionStoreKind::Memory
+    )]
+    transaction_store: TransactionStoreKind,
+
+    /// Directory of the transaction store when stored on disk; its previous content is discarded.
+    /// With `--namespace-by-file`, each file has its own store in a subdirectory named after the
+    /// file number.
+    #[clap(long, value_name = "PATH", required_if_eq("transaction_store", "sled"))]
+    transaction_store_path: Option<PathBuf>,
+
+    /// Write a summary of the transaction errors to stderr at the end of the run: the number of
+    /// errors of each kind, the most frequent first, with a sample transaction ID.
+    #[clap(long)]
+    explain_errors: bool,
+
+    /// Maximum number of disputes that can be open at the same time across all clients, bounding
+    /// the memory used to track them; further disputes are rejected until some are closed.
+    #[clap(long, value_name = "N")]
+    max_open_disputes: Option<usize>,
+
+    /// Exit with the number of transactions that could not be processed plus one as the status,
+    /// capped at 125, so that scripts can branch on it. Status 0 means no warnings; status 1 is
+    /// kept for fatal errors.
+    #[clap(long)]
+    encode_warnings_in_exit: bool,
+
+    /// Reject the transactions whose timestamp is after the current time, which likely indicates
+    /// a bug upstream. Transactions without a timestamp are not affected.
+    #[clap(long)]
+    reject_future_timestamps: bool,
+
+    /// Write the locked flag as 1 or 0 instead of true or false.
+    #[clap(long)]
+    bool_as_int: bool,
+
+    /// Write the sum of the rounded available and held funds as the total. By default, the total
+    /// is rounded on its own and may differ from that sum by the last decimal place.
+    #[clap(long)]
+    consistent_totals: bool,
+
+    /// Result of a previous run, used as the opening balances of the clients.
+    #[clap(long, value_name = "PATH")]
+    opening_balances: Option<PathBuf>,
+
+    /// Write the net change of each client's account produced by this run to a CSV file. Only
+    /// clients whose account changed are written.
+    #[clap(long, value_name = "PATH")]
+    delta_output: Option<PathBuf>,
+
+    /// Start from a processing state previously written with `--dump-state` instead of an empty
+    /// one.
+    #[clap(long, value_name = "PATH", conflicts_with = "opening_balances")]
+    resume_state: Option<PathBuf>,
+
+    /// Write the whole processing state (clients, stored transactions and their dispute states) as
+    /// JSON to a file at the end of the run.
+    #[clap(long, value_name = "PATH")]
+    dump_state: Option<PathBuf>,
+
+    /// Reject disputes, resolves and chargebacks. Deposits and withdrawals are then not kept in
+    /// memory, as nothing can refer to them; only their IDs are, so that they cannot be reused.
+    #[clap(long)]
+    no_disputes: bool,
+
+    /// Number of seconds between two snapshots in follow mode.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        requires = "follow"
+    )]
+    snapshot_interval: u64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a JSON Schema describing the transaction records expected as input.
+    Schema,
+
+    /// Merge several previously written results into one, summing the balances of clients that
+    /// appear in more than one of them.
+    Merge {
+        /// Result files to merge.
+        #[clap(required = true)]
+        result_filepaths: Vec<PathBuf>,
+    },
+
+    /// Write synthetic but valid transactions, for benchmarking or testing. The same seed always
+    /// produces the same output.
+    Generate {
+        /// Number of clients.
+        #[clap(long)]
+        clients: u16,
+        /// Number of transactions.
+        #[clap(long)]
+        txns: u32,
+        /// Seed of the random number generator.
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+/// Returns the exit status encoding a number of warnings: 0 without warnings, otherwise the
+/// number of warnings plus one, capped at `MAX_WARNINGS_EXIT_CODE`. Status 1 is left to fatal
+/// errors.
+pub(crate) fn warnings_exit_code(warnings: usize) -> u8 {
+    if warnings == 0 {
+        return 0;
+    }
+    u8::try_from(warnings)
+        .ok()
+        .and_then(|warnings| warnings.checked_add(1))
+        .unwrap_or(u8::MAX)
+        .min(MAX_WARNINGS_EXIT_CODE)
+}
+
+/// Runs the command line application and returns its exit status.
+pub fn run() -> Result<ExitCode, Error> {
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Schema) => return write_schema(io::stdout()).map(|()| ExitCode::SUCCESS),
+        Some(Command::Generate {
+            clients,
+            txns,
+            seed,
+        }) => {
+            return generate_transactions(clients, txns, seed, io::stdout())
+                .map(|()| ExitCode::SUCCESS)
+        }
+        Some(Command::Merge { result_filepaths }) => {
+            check_duplicate_files(&result_filepaths)?;
+            let mut clients = HashMap::default();
+            for result_filepath in result_filepaths {
+                let file = File::open(&result_filepath)
+                    .map_err(|err| Error::ResultFileReadError(result_filepath, err))?;
+                merge_result(file, &mut clients)?;
+            }
+            return write_result(&clients, None, &ResultOptions::default(), io::stdout())
+                .map(|()| ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+    check_duplicate_files(&args.transactions_filepaths)?;
+    let mut files: Vec<Box<dyn Read>> = Vec::new();
+    if args.stream || args.transactions_filepaths.is_empty() {
+        files.push(Box::new(io::stdin().lock()));
+    }
+    for transactions_filepath in &args.transactions_filepaths {
+        let file = File::open(transactions_filepath)
+            .map_err(|err| Error::TransactionFileReadError(transactions_filepath.clone(), err))?;
+        files.push(Box::new(file));
+    }
+    let rejected_records = match &args.dump_errors_csv {
+        Some(dump_errors_csv) => {
+            let file = File::create(dump_errors_csv)
+                .map_err(|err| Error::FileWriteError(dump_errors_csv.clone(), err))?;
+            Some(RejectedRecordWriter::new(Box::new(file)))
+        }
+        None => None,
+    };
+    let mut options = ProcessingOptions {
+        echo_input: args.echo_input,
+        hold_new_deposits: args.hold_new_deposits,
+        no_disputes: args.no_disputes,
+        daily_withdrawal_limit: args.daily_withdrawal_limit.map(MoneyAmount),
+        allow_resolve_after_chargeback: args.allow_resolve_after_chargeback,
+        strict_columns: args.input_has_trailing_amount_always,
+        tally_locked_rejections: args.tally_locked_rejections,
+        error_verbosity: if args.quiet_errors {
+            ErrorVerbosity::Quiet
+        } else if args.verbose_errors {
+            ErrorVerbosity::Verbose
+        } else {
+            ErrorVerbosity::Normal
+        },
+        rejected_records,
+        warn_precision_loss: args.warn_precision_loss,
+        max_disputes_per_client: args.max_disputes_per_client,
+        max_open_disputes: args.max_open_disputes,
+        trim: args.trim,
+        reject_future_timestamps: args
+            .reject_future_timestamps
+            .then(|| Box::new(SystemClock) as Box<dyn Clock>),
+        ..Default::default()
+    };
+    let result_options = ResultOptions {
+        bool_as_int: args.bool_as_int,
+        consistent_totals: args.consistent_totals,
+    };
+    if args.namespace_by_file {
+        let mut states = process_namespaced_transactions(
+            files,
+            args.transaction_store,
+            args.transaction_store_path.as_deref(),
+            &mut options,
+            io::stderr(),
+        )?;
+        options.flush_rejected_records()?;
+        let stats = finish_run(&args, &mut states)?;
+        write_namespaced_result(
+            &states,
+            args.verbose,
+            args.format,
+            &result_options,
+            io::stdout(),
+        )?;
+        if let Some(delta_output) = &args.delta_output {
+            let delta_file = File::create(delta_output)
+                .map_err(|err| Error::FileWriteError(delta_output.clone(), err))?;
+            write_namespaced_deltas(&states, delta_file)?;
+        }
+        if let Some(json_dir) = &args.json_dir {
+            // Each tenant has its own directory, as client IDs are only unique within a tenant
+            for (index, state) in states.iter().enumerate() {
+                let tenant_dir = json_dir.join((index + 1).to_string());
+                write_json_dir(&state.clients, &tenant_dir, &result_options)?;
+            }
+        }
+        return Ok(exit_status(&args, &stats));
+    }
+    let mut state = match &args.resume_state {
+        Some(resume_state) => {
+            let json = fs::read_to_string(resume_state)
+                .map_err(|err| Error::FileReadError(resume_state.clone(), err))?;
+            ProcessingState::from_json(&json)?
+        }
+        None => ProcessingState::default(),
+    };
+    if let Some(opening_balances) = &args.opening_balances {
+        let opening_balances_file = File::open(opening_balances)
+            .map_err(|err| Error::ResultFileReadError(opening_balances.clone(), err))?;
+        merge_result(opening_balances_file, &mut state.clients)?;
+        state.report.opening_funds = sum_total_funds(&state.clients);
+    }
+    select_transaction_store(
+        &mut state,
+        args.transaction_store,
+        args.transaction_store_path.as_deref(),
+    )?;
+    let opening_clients = args.delta_output.is_some().then(|| state.clients.clone());
+    if args.follow {
+        let snapshot_interval = Duration::from_secs(args.snapshot_interval);
+        let mut follower = Follower::new(single_file(files)?, state);
+        let mut last_snapshot = Instant::now();
+        loop {
+            follower.poll(&mut options, io::stderr())?;
+            options.flush_rejected_records()?;
+            if last_snapshot.elapsed() >= snapshot_interval {
+                let state = &follower.state;
+                let disputed_funds = args.verbose.then(|| state.disputed_funds()).transpose()?;
+                write_formatted_result(
+                    args.format,
+                    &state.clients,
+                    disputed_funds.as_ref(),
+                    &result_options,
+                    io::stdout(),
+                )?;
+                last_snapshot = Instant::now();
+            }
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+    if args.assume_sorted_by_client {
+        process_sorted_transactions_into(
+            single_file(files)?,
+            &mut state,
+            &mut options,
+            args.verbose,
+            &result_options,
+            io::stdout(),
+            io::stderr(),
+        )?;
+    } else {
+        process_chained_transactions_into(files, &mut state, &mut options, io::stderr())?;
+    }
+    options.flush_rejected_records()?;
+    let stats = finish_run(&args, slice::from_mut(&mut state))?;
+    let disputed_funds = args.verbose.then(|| state.disputed_funds()).transpose()?;
+
+    // Clients have already been written when processing transactions grouped by client
+    if !args.assume_sorted_by_client {
+        write_formatted_result(
+            args.format,
+            &state.clients,
+            disputed_funds.as_ref(),
+            &result_options,
+            io::stdout(),
+        )?;
+    }
+
+    if let (Some(delta_output), Some(opening_clients)) = (&args.delta_output, opening_clients) {
+        let delta_file = File::create(delta_output)
+            .map_err(|err| Error::FileWriteError(delta_output.clone(), err))?;
+        write_deltas(&opening_clients, &state.clients, delta_file)?;
+    }
+
+    if let Some(json_dir) = &args.json_dir {
+        write_json_dir(&state.clients, json_dir, &result_options)?;
+    }
+
+    if let Some(dump_state) = &args.dump_state {
+        fs::write(dump_state, state.to_json()?)
+            .map_err(|err| Error::FileWriteError(dump_state.clone(), err))?;
+    }
+
+    Ok(exit_status(&args, &stats))
+}
+
+/// Ends a run whose transactions have all been processed, with one state per tenant: accrues the
+/// interest, audits the funds and writes the requested summaries to stderr. Returns the stats of
+/// the whole run.
+fn finish_run(args: &Args, states: &mut [ProcessingState]) -> Result<ProcessingStats, Error> {
+    let mut stats = ProcessingStats::default();
+    for state in states.iter_mut() {
+        if let Some(interest_rate_bps) = args.interest_rate_bps {
+            state.accrue_interest(interest_rate_bps)?;
+        }
+        if args.audit_funds {
+            state.audit_funds()?;
+        }
+        stats.merge(&state.report.stats);
+    }
+
+    if args.report_peak_sizes {
+        eprintln!(
+            "Peak sizes: {} transactions, {} clients",
+            stats.peak_transactions, stats.peak_clients
+        );
+    }
+    if args.quiet_errors {
+        eprintln!("{} transactions could not be processed", stats.rejected());
+    }
+    if args.explain_errors {
+        for (kind, count) in stats.ranked_rejections() {
+            if let Some(transaction_id) = stats.sample_transaction_ids.get(kind) {
+                eprintln!(
+                    "{}: {} errors, e.g. transaction {}",
+                    kind, count, transaction_id
+                );
+            }
+        }
+    }
+    eprintln!("{}", stats);
+    if args.tally_locked_rejections {
+        for (index, state) in states.iter().enumerate() {
+            let mut locked_rejections: Vec<_> = state.report.locked_rejections.iter().collect();
+            locked_rejections.sort();
+            for (&client_id, count) in locked_rejections {
+                let client_id = if args.namespace_by_file {
+                    NamespacedClientId {
+                        file_number: index + 1,
+                        client_id,
+                    }
+                    .to_string()
+                } else {
+                    client_id.to_string()
+                };
+                eprintln!("Client {client_id}: {count} transactions rejected due to lock");
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Returns the exit status of a successful run, encoding its number of warnings if requested.
+fn exit_status(args: &Args, stats: &ProcessingStats) -> ExitCode {
+    if args.encode_warnings_in_exit {
+        return ExitCode::from(warnings_exit_code(stats.rejected()));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Returns the only transaction file of a processing mode that cannot process several of them.
+fn single_file(files: Vec<Box<dyn Read>>) -> Result<Box<dyn Read>, Error> {
+    match <[Box<dyn Read>; 1]>::try_from(files) {
+        Ok([file]) => Ok(file),
+        Err(files) if files.is_empty() => Err(Error::MissingTransactionFile),
+        Err(_) => Err(Error::MultipleTransactionFiles),
+    }
+}
+
+/// Checks that no file is given more than once, as its content would then be applied twice.
+/// Paths are canonicalized so that different paths to the same file are detected too.
+pub(crate) fn check_duplicate_files(paths: &[PathBuf]) -> Result<(), Error> {
+    let mut canonical_paths = HashSet::new();
+    for path in paths {
+        // Paths that cannot be canonicalized are kept as is; opening them will report the error
+        let canonical_path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        if !canonical_paths.insert(canonical_path) {
+            return Err(Error::DuplicateInputFile(path.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a JSON Schema describing a transaction record to a writer.
+/// The schema is generated from `TransactionRecord` itself so that it always matches the parser.
+pub(crate) fn write_schema<W: Write>(mut writer: W) -> Result<(), Error> {
+    let schema = schemars::schema_for!(TransactionRecord);
+    serde_json::to_writer_pretty(&mut writer, &schema).map_err(Error::JsonError)?;
+    writeln!(writer).map_err(Error::OutputWriteError)?;
+
+    Ok(())
+}
+
+/// Account state of a client, as tracked by the transaction generator.
+#[derive(Default)]
+struct GeneratedClient {
+    /// Available funds.
+    available_funds: Decimal,
+    /// Deposits that can be disputed.
+    deposits: Vec<(u32, Decimal)>,
+    /// Deposits under an open dispute.
+    disputes: Vec<(u32, Decimal)>,
+}
+
+/// Writes synthetic transactions to a writer: deposits, withdrawals, disputes and resolves.
+/// Transactions are valid, meaning that withdrawals and disputes never exceed the available funds.
+/// The output only depends on the parameters, so the same seed always produces the same output.
+pub(crate) fn generate_transactions<W: Write>(
+    client_count: u16,
+    transaction_count: u32,
+    seed: u64,
+    writer: W,
+) -> Result<(), Error> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut clients: Vec<GeneratedClient> = (0..client_count).map(|_| Default::default()).collect();
+    let mut writer = csv::Writer::from_writer(writer);
+    writer
+        .write_record(["type", "client", "tx", "amount"])
+        .map_err(Error::WriteError)?;
+
+    // IDs are never reused, even if they run out
+    let mut transaction_ids = 1..=u32::MAX;
+    for _ in 0..transaction_count {
+        if clients.is_empty() {
+            break;
+        }
+        let index = rng.gen_range(0..clients.len());
+        let client_id = index + 1;
+        let client = &mut clients[index];
+        let roll = rng.gen_range(0..100);
+        // A random deposit to dispute, if it can be covered by the available funds
+        let disputed_position = if (25..35).contains(&roll) && !client.deposits.is_empty() {
+            let position = rng.gen_range(0..client.deposits.len());
+            (client.deposits[position].1 <= client.available_funds).then_some(position)
+        } else {
+            None
+        };
+        let row = if roll < 25 && client.available_funds > Decimal::ZERO {
+            // Withdraw part of the available funds, at least the smallest amount
+            let share = Decimal::new(rng.gen_range(1..=100), 2);
+            let amount = (client.available_funds * share)
+                .round_dp(DECIMAL_PRECISION)
+                .max(Decimal::new(1, DECIMAL_PRECISION));
+            client.available_funds -= amount;
+            let id = transaction_ids
+                .next()
+                .ok_or(Error::TransactionIdsExhausted)?;
+            ("withdrawal", id, Some(amount))
+        } else if let Some(position) = disputed_position {
+            let deposit = client.deposits.swap_remove(position);
+            client.available_funds -= deposit.1;
+            client.disputes.push(deposit);
+            ("dispute", deposit.0, None)
+        } else if (35..40).contains(&roll) && !client.disputes.is_empty() {
+            let position = rng.gen_range(0..client.disputes.len());
+            let dispute = client.disputes.swap_remove(position);
+            client.available_funds += dispute.1;
+            ("resolve", dispute.0, None)
+        } else {
+            let amount = Decimal::new(rng.gen_range(1..=10_000_000), DECIMAL_PRECISION);
+            client.available_funds += amount;
+            let id = transaction_ids
+                .next()
+                .ok_or(Error::TransactionIdsExhausted)?;
+            client.deposits.push((id, amount));
+            ("deposit", id, Some(amount))
+        };
+        writer
+            .serialize((row.0, client_id, row.1, row.2))
+            .map_err(Error::SerializationError)?;
+    }
+
+    writer.flush().map_err(Error::FlushError)?;
+
+    Ok(())
+}
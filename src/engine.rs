@@ -0,0 +1,96 @@
+//! Processing records one at a time, for library users.
+
+use crate::{
+    error::Error,
+    processing::{process_transaction, ProcessingOptions},
+    state::{ProcessingState, ProcessingStats},
+    transaction::TransactionRecord,
+    types::{Client, ClientId},
+};
+use std::collections::HashMap;
+
+/// A payment engine, processing transaction records one at a time.
+#[derive(Default)]
+pub struct PaymentEngine {
+    /// Clients and stored transactions.
+    state: ProcessingState,
+    /// Options controlling how transactions are processed; the defaults are used.
+    options: ProcessingOptions,
+}
+
+impl PaymentEngine {
+    /// Creates an engine without any client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes a transaction record.
+    /// An error means that the transaction has been rejected; other records can still be
+    /// processed.
+    pub fn process_record(&mut self, record: TransactionRecord) -> Result<(), Error> {
+        let transaction_id = record.id;
+        let type_string = record.type_string.clone();
+        let result = process_transaction(record, &mut self.state, &mut self.options);
+        self.state
+            .report
+            .stats
+            .count(&type_string, transaction_id, &result);
+        self.state.update_peak_sizes();
+        result
+    }
+
+    /// Returns the account of every client seen so far.
+    pub fn accounts(&self) -> &HashMap<ClientId, Client> {
+        &self.state.clients
+    }
+
+    /// Returns the counts of the records processed so far, and the peak sizes of the state.
+    pub fn stats(&self) -> &ProcessingStats {
+        &self.state.report.stats
+    }
+
+    /// Ends the processing and returns the resulting accounts.
+    pub fn into_result(self) -> ProcessingResult {
+        ProcessingResult::new(self.state.clients)
+    }
+}
+
+/// The accounts of the clients at the end of the processing, both as a map for fast lookup and as
+/// a vector sorted by client ID for output. Both views are built once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessingResult {
+    /// The account of every client.
+    clients: HashMap<ClientId, Client>,
+    /// Snapshots of the accounts, sorted by client ID.
+    sorted_clients: Vec<(ClientId, Client)>,
+}
+
+impl ProcessingResult {
+    /// Builds both views of the accounts of the clients.
+    pub fn new(clients: HashMap<ClientId, Client>) -> Self {
+        let mut sorted_clients: Vec<_> = clients
+            .iter()
+            .map(|(client_id, client)| (*client_id, client.clone()))
+            .collect();
+        sorted_clients.sort_by_key(|(client_id, _)| *client_id);
+        Self {
+            clients,
+            sorted_clients,
+        }
+    }
+
+    /// Returns the account of a client.
+    pub fn get(&self, client_id: ClientId) -> Option<&Client> {
+        self.clients.get(&client_id)
+    }
+
+    /// Returns the account of every client, for fast lookup.
+    pub fn clients(&self) -> &HashMap<ClientId, Client> {
+        &self.clients
+    }
+
+    /// Returns the account of every client, sorted by client ID.
+    pub fn sorted_clients(&self) -> &[(ClientId, Client)] {
+        &self.sorted_clients
+    }
+}
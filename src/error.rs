@@ -0,0 +1,194 @@
+//! The errors of the application.
+
+use crate::types::{ClientId, MoneyAmount, TransactionId};
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Any error that can be triggered by this application.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed reading transaction file {0}: {1}")]
+    TransactionFileReadError(PathBuf, io::Error),
+
+    #[error("write error: {0}")]
+    WriteError(csv::Error),
+
+    #[error("flush error: {0}")]
+    FlushError(io::Error),
+
+    #[error("serialization error: {0}")]
+    SerializationError(csv::Error),
+
+    #[error("failed parsing transaction: {0}")]
+    ParsingError(csv::Error),
+
+    #[error("deposit without amount")]
+    DepositWithoutAmount,
+
+    #[error("withdrawal without amount")]
+    WithdrawalWithoutAmount,
+
+    #[error("transaction without amount")]
+    TransactionWithoutAmount,
+
+    #[error("unknown transaction ID: {0}")]
+    UnknownTransactionId(TransactionId),
+
+    #[error("client {0}: withdrawal without enough available funds, needed {1}, available {2}")]
+    NotEnoughAvailableFunds(ClientId, MoneyAmount, MoneyAmount),
+
+    #[error("client {0}: balance overflow")]
+    BalanceOverflow(ClientId),
+
+    #[error("transaction ID {0} is already used")]
+    DuplicateTransactionId(TransactionId),
+
+    #[error("negative ID: {0}")]
+    NegativeId(String),
+
+    #[error("client {0} has opened too many disputes")]
+    TooManyDisputes(ClientId),
+
+    #[error("transaction {0}: too many disputes are open")]
+    TooManyOpenDisputes(TransactionId),
+
+    #[error("transaction {0} does not belong to client {1}")]
+    TransactionClientMismatch(TransactionId, ClientId),
+
+    #[error("transaction {0} already under dispute")]
+    TransactionAlreadyUnderDispute(TransactionId),
+
+    #[error("transaction {0} not under dispute")]
+    TransactionNotUnderDispute(TransactionId),
+
+    #[error("transaction {0} is not pending")]
+    TransactionNotPending(TransactionId),
+
+    #[error("transaction {0} is pending and cannot be disputed")]
+    TransactionPending(TransactionId),
+
+    #[error("transaction {0} has been voided")]
+    TransactionVoided(TransactionId),
+
+    #[error("amount must be greater than zero")]
+    InvalidAmount(MoneyAmount),
+
+    #[error("client account {0} is locked")]
+    ClientLocked(ClientId),
+
+    #[error("unknown transaction type: {0}")]
+    UnknownTransactionType(String),
+
+    #[error("log write error: {0}")]
+    LogWriteError(io::Error),
+
+    #[error("no transaction file given")]
+    MissingTransactionFile,
+
+    #[error("several transaction files cannot be followed or assumed to be sorted by client")]
+    MultipleTransactionFiles,
+
+    #[error("JSON error: {0}")]
+    JsonError(serde_json::Error),
+
+    #[cfg(feature = "sled")]
+    #[error("transaction store error: {0}")]
+    TransactionStoreError(sled::Error),
+
+    #[error("the transaction store is corrupted")]
+    CorruptedTransactionStore,
+
+    #[error("no path given for the transaction store")]
+    MissingTransactionStorePath,
+
+    #[error("output write error: {0}")]
+    OutputWriteError(io::Error),
+
+    #[error("failed reading result file {0}: {1}")]
+    ResultFileReadError(PathBuf, io::Error),
+
+    #[error("read error: {0}")]
+    ReadError(io::Error),
+
+    #[error("transaction {0}: disputes are disabled")]
+    DisputesDisabled(TransactionId),
+
+    #[error("invalid UTF-8 in the record starting at byte {0}")]
+    InvalidUtf8(u64),
+
+    #[error("transaction {0} is dated in the future")]
+    FutureTimestamp(TransactionId),
+
+    #[error("transaction {0} has no timestamp")]
+    MissingTimestamp(TransactionId),
+
+    #[error("client {0}: withdrawal exceeding the daily limit of {1}")]
+    DailyWithdrawalLimitExceeded(ClientId, MoneyAmount),
+
+    #[error("transaction {0} has been charged back and cannot be resolved")]
+    CannotResolveChargedBack(TransactionId),
+
+    #[error("transaction {0}: expected {1} columns, found {2}")]
+    MissingColumns(TransactionId, usize, usize),
+
+    #[error("no transaction ID left to generate transactions")]
+    TransactionIdsExhausted,
+
+    #[error("file {0} is given more than once")]
+    DuplicateInputFile(PathBuf),
+
+    #[error("funds audit failed: clients should have {0} in total but have {1}")]
+    FundsAuditFailed(MoneyAmount, MoneyAmount),
+
+    #[error("failed reading file {0}: {1}")]
+    FileReadError(PathBuf, io::Error),
+
+    #[error("failed writing file {0}: {1}")]
+    FileWriteError(PathBuf, io::Error),
+
+    #[error("disputed amount {1} exceeds the amount of transaction {0}")]
+    DisputedAmountTooLarge(TransactionId, MoneyAmount),
+
+    #[error(
+        "client {0} appears again after its transactions ended; the input is not grouped by client"
+    )]
+    ClientNotGrouped(ClientId),
+
+    #[error("column {0} of a rejected record is not in the header of the rejected records file")]
+    RejectedRecordColumnMismatch(String),
+}
+
+impl Error {
+    /// Converts an error triggered while reading transactions, reporting invalid UTF-8 input with
+    /// the position of the record containing it.
+    pub(crate) fn from_transaction_parsing(err: csv::Error) -> Self {
+        match err.kind() {
+            csv::ErrorKind::Utf8 { pos: Some(pos), .. } => Error::InvalidUtf8(pos.byte()),
+            _ => Error::ParsingError(err),
+        }
+    }
+
+    /// Returns the name of the kind of this error, like `UnknownTransactionId`.
+    pub(crate) fn kind(&self) -> String {
+        // The derived debug representation starts with the name of the variant
+        let mut kind = format!("{:?}", self);
+        kind.truncate(kind.find('(').unwrap_or(kind.len()));
+        kind
+    }
+
+    /// Is this error caused by the reader of the output going away, like a closed pipe?
+    pub(crate) fn is_broken_pipe(&self) -> bool {
+        let io_error_kind = match self {
+            Error::FlushError(err) | Error::OutputWriteError(err) => Some(err.kind()),
+            Error::WriteError(err) | Error::SerializationError(err) => match err.kind() {
+                csv::ErrorKind::Io(err) => Some(err.kind()),
+                _ => None,
+            },
+            // The JSON result is written by serde_json, which wraps the I/O errors
+            Error::JsonError(err) => err.io_error_kind(),
+            _ => None,
+        };
+        io_error_kind == Some(io::ErrorKind::BrokenPipe)
+    }
+}
@@ -0,0 +1,548 @@
+//! Reading transactions from CSV, from one or several inputs or from a growing file.
+
+use crate::{
+    error::Error,
+    processing::{log_transaction_error, process_record, ErrorVerbosity, ProcessingOptions},
+    state::{ProcessingReport, ProcessingState, ProcessingStats},
+    transaction::TransactionRecord,
+    types::{Client, ClientId},
+};
+#[cfg(feature = "cli")]
+use crate::{
+    output::{write_result_header, write_result_record, ResultOptions},
+    processing::sum_amounts,
+    store::{select_transaction_store, TransactionStoreKind},
+    transaction::DisputedState,
+};
+use csv::Trim;
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+#[cfg(feature = "cli")]
+use std::{collections::HashSet, mem, path::Path};
+
+/// Which parts of the transaction input have their surrounding spaces and tabs trimmed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub(crate) enum TrimMode {
+    /// Trim the header and the fields.
+    #[default]
+    All,
+
+    /// Only trim the header.
+    #[cfg(feature = "cli")]
+    Headers,
+
+    /// Only trim the fields.
+    #[cfg(feature = "cli")]
+    Fields,
+
+    /// Keep the input as is.
+    #[cfg(feature = "cli")]
+    None,
+}
+
+#[cfg(feature = "cli")]
+impl TrimMode {
+    /// Does this mode trim the header?
+    fn trims_headers(self) -> bool {
+        matches!(self, TrimMode::All | TrimMode::Headers)
+    }
+
+    /// Does this mode trim the fields?
+    fn trims_fields(self) -> bool {
+        matches!(self, TrimMode::All | TrimMode::Fields)
+    }
+}
+
+impl From<TrimMode> for Trim {
+    fn from(trim_mode: TrimMode) -> Self {
+        match trim_mode {
+            TrimMode::All => Trim::All,
+            #[cfg(feature = "cli")]
+            TrimMode::Headers => Trim::Headers,
+            #[cfg(feature = "cli")]
+            TrimMode::Fields => Trim::Fields,
+            #[cfg(feature = "cli")]
+            TrimMode::None => Trim::None,
+        }
+    }
+}
+
+/// Writes the records that have not been applied, with the header of the input, so that they can
+/// be submitted again once fixed. Nothing is written when every record is applied.
+pub(crate) struct RejectedRecordWriter {
+    /// The CSV writer; records can have fewer columns than the header, like in the input.
+    writer: csv::Writer<Box<dyn Write>>,
+    /// The header written before the first rejected record, if any.
+    header: Option<csv::StringRecord>,
+}
+
+impl RejectedRecordWriter {
+    #[cfg(feature = "cli")]
+    pub(crate) fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_writer(writer),
+            header: None,
+        }
+    }
+
+    /// Writes a rejected record, preceded by the input header if it is the first one.
+    /// Records from an input whose columns are in another order are reordered to match the
+    /// written header; a column missing from that header is an error, as it would be lost.
+    fn write(
+        &mut self,
+        record: &csv::StringRecord,
+        headers: &csv::StringRecord,
+    ) -> Result<(), Error> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                self.writer
+                    .write_record(headers)
+                    .map_err(Error::WriteError)?;
+                self.header.insert(headers.clone())
+            }
+        };
+        if header == headers {
+            return self.writer.write_record(record).map_err(Error::WriteError);
+        }
+
+        if let Some(column) = headers
+            .iter()
+            .find(|column| !header.iter().any(|name| name.trim() == column.trim()))
+        {
+            return Err(Error::RejectedRecordColumnMismatch(
+                column.trim().to_string(),
+            ));
+        }
+        let fields = header.iter().map(|name| {
+            headers
+                .iter()
+                .position(|column| column.trim() == name.trim())
+                .and_then(|index| record.get(index))
+                .unwrap_or_default()
+        });
+        self.writer.write_record(fields).map_err(Error::WriteError)
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(Error::FlushError)
+    }
+}
+
+/// Returns an error naming the field and the value if the record has a negative client or
+/// transaction ID. IDs are unsigned, so such a record otherwise fails with a generic parsing error.
+fn negative_id(record: &csv::StringRecord, headers: &csv::StringRecord) -> Option<Error> {
+    ["client", "tx"].into_iter().find_map(|field| {
+        let position = headers.iter().position(|header| header == field)?;
+        let value = record.get(position)?;
+        let is_negative = value
+            .strip_prefix('-')
+            .is_some_and(|magnitude| magnitude.parse::<u64>().is_ok());
+        is_negative.then(|| Error::NegativeId(format!("{} {}", field, value)))
+    })
+}
+
+/// Deserializes a CSV record using the given header.
+/// Returns `None` if the record is rejected, after writing the reason to the log.
+fn parse_csv_record<L: Write>(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+    options: &ProcessingOptions,
+    report: &mut ProcessingReport,
+    log: &mut L,
+) -> Result<Option<TransactionRecord>, Error> {
+    let transaction_record: TransactionRecord =
+        record.deserialize(Some(headers)).map_err(|err| {
+            negative_id(record, headers).unwrap_or_else(|| Error::from_transaction_parsing(err))
+        })?;
+    // Missing trailing columns are allowed by default, as the reader is flexible
+    if options.strict_columns && record.len() < headers.len() {
+        let err = Error::MissingColumns(transaction_record.id, headers.len(), record.len());
+        let raw_record =
+            (options.error_verbosity == ErrorVerbosity::Verbose).then(|| format!("{:?}", record));
+        log_transaction_error(log, &err, raw_record.as_deref(), options)?;
+        report.stats.count(
+            &transaction_record.type_string,
+            transaction_record.id,
+            &Err(err),
+        );
+        return Ok(None);
+    }
+    Ok(Some(transaction_record))
+}
+
+/// Deserializes a CSV record using the given header and processes it.
+fn process_csv_record<L: Write>(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    log: &mut L,
+) -> Result<(), Error> {
+    let applied = match parse_csv_record(record, headers, options, &mut state.report, log)? {
+        Some(transaction_record) => process_record(transaction_record, state, options, log)?,
+        None => false,
+    };
+    if !applied {
+        write_rejected_record(record, headers, options)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a record that has not been applied to the rejected records, if requested.
+fn write_rejected_record(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+    options: &mut ProcessingOptions,
+) -> Result<(), Error> {
+    match &mut options.rejected_records {
+        Some(rejected_records) => rejected_records.write(record, headers),
+        None => Ok(()),
+    }
+}
+
+/// Returns a CSV reader builder configured for transaction input.
+pub(crate) fn transaction_reader_builder(trim: TrimMode) -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder
+        .trim(trim.into()) // ignore spaces/tabs
+        .flexible(true); // allow missing fields (amount for instance)
+    builder
+}
+
+/// Reads the transactions from a reader and processes them.
+/// We could have split this function into two: reading and processing, but it is
+/// more efficient to process the transactions on the fly rather than storing
+/// all of them first.
+/// This function returns a map of all clients and the counts of the processed records.
+/// Transaction errors are written to stderr.
+pub fn process_transactions<R: Read>(
+    reader: R,
+) -> Result<(HashMap<ClientId, Client>, ProcessingStats), Error> {
+    let state =
+        process_transactions_with_options(reader, &mut ProcessingOptions::default(), io::stderr())?;
+    Ok((state.clients, state.report.stats))
+}
+
+/// Same as `process_transactions`, but with explicit processing options.
+/// Diagnostics (echoed records, transaction errors) are written to `log`.
+pub(crate) fn process_transactions_with_options<R: Read, L: Write>(
+    reader: R,
+    options: &mut ProcessingOptions,
+    log: L,
+) -> Result<ProcessingState, Error> {
+    let mut state = ProcessingState::default();
+    process_transactions_into(reader, &mut state, options, log)?;
+
+    Ok(state)
+}
+
+/// Reads the transactions from a reader and processes them into an existing state.
+pub(crate) fn process_transactions_into<R: Read, L: Write>(
+    reader: R,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    mut log: L,
+) -> Result<(), Error> {
+    let mut reader = transaction_reader_builder(options.trim).from_reader(reader);
+    let headers = reader
+        .headers()
+        .map_err(Error::from_transaction_parsing)?
+        .clone();
+
+    for record in reader.records() {
+        let record = record.map_err(Error::from_transaction_parsing)?;
+        process_csv_record(&record, &headers, state, options, &mut log)?;
+    }
+
+    Ok(())
+}
+
+/// Reads transactions grouped by client from a reader and processes them into an existing state.
+/// Each client is finalized as soon as its transactions end: its result is written and it is
+/// removed from the state along with its transactions, so disputes cannot target a transaction of
+/// another client. Returns an error if a finalized client appears again.
+/// The reader of the result may stop reading early, like `head` in a pipeline: a broken pipe is
+/// not an error, the remaining transactions are processed without writing their result.
+#[cfg(feature = "cli")]
+pub(crate) fn process_sorted_transactions_into<R: Read, W: Write, L: Write>(
+    reader: R,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    with_disputed_funds: bool,
+    result_options: &ResultOptions,
+    writer: W,
+    mut log: L,
+) -> Result<(), Error> {
+    let mut reader = transaction_reader_builder(options.trim).from_reader(reader);
+    let headers = reader
+        .headers()
+        .map_err(Error::from_transaction_parsing)?
+        .clone();
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    let result = write_result_header(&mut writer, with_disputed_funds);
+    let mut writer = Some(writer);
+    close_on_broken_pipe(result, &mut writer)?;
+
+    let mut current_client = None;
+    let mut finalized_clients = HashSet::new();
+    for record in reader.records() {
+        let record = record.map_err(Error::from_transaction_parsing)?;
+        let Some(transaction_record) =
+            parse_csv_record(&record, &headers, options, &mut state.report, &mut log)?
+        else {
+            write_rejected_record(&record, &headers, options)?;
+            continue;
+        };
+        let client_id = transaction_record.client_id;
+        if current_client != Some(client_id) {
+            if finalized_clients.contains(&client_id) {
+                return Err(Error::ClientNotGrouped(client_id));
+            }
+            if let Some(previous_client) = current_client.replace(client_id) {
+                finalize_client(
+                    previous_client,
+                    state,
+                    with_disputed_funds,
+                    result_options,
+                    &mut writer,
+                )?;
+                finalized_clients.insert(previous_client);
+            }
+        }
+        if !process_record(transaction_record, state, options, &mut log)? {
+            write_rejected_record(&record, &headers, options)?;
+        }
+    }
+    if let Some(last_client) = current_client {
+        finalize_client(
+            last_client,
+            state,
+            with_disputed_funds,
+            result_options,
+            &mut writer,
+        )?;
+    }
+
+    if let Some(result_writer) = &mut writer {
+        let result = result_writer.flush().map_err(Error::FlushError);
+        close_on_broken_pipe(result, &mut writer)?;
+    }
+
+    Ok(())
+}
+
+/// Stops writing the result if its reader went away: the writer is dropped on a broken pipe,
+/// which is not an error. Other errors are returned.
+#[cfg(feature = "cli")]
+fn close_on_broken_pipe<W: Write>(
+    result: Result<(), Error>,
+    writer: &mut Option<csv::Writer<W>>,
+) -> Result<(), Error> {
+    match result {
+        Err(err) if err.is_broken_pipe() => {
+            *writer = None;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Writes the result of a client whose transactions have ended and removes it from the state.
+/// Nothing is written once the result writer has been closed.
+#[cfg(feature = "cli")]
+fn finalize_client<W: Write>(
+    client_id: ClientId,
+    state: &mut ProcessingState,
+    with_disputed_funds: bool,
+    result_options: &ResultOptions,
+    writer: &mut Option<csv::Writer<W>>,
+) -> Result<(), Error> {
+    let disputes: Vec<_> = state
+        .transactions
+        .remove_client(client_id)?
+        .into_iter()
+        .filter(|transaction| transaction.disputed == DisputedState::Disputed)
+        .collect();
+    state.open_disputes = state.open_disputes.saturating_sub(disputes.len());
+    let disputed_funds = with_disputed_funds.then(|| {
+        sum_amounts(
+            disputes
+                .iter()
+                .map(|transaction| transaction.disputed_amount),
+        )
+    });
+    state.recent_deposits.remove(&client_id);
+    state.daily_withdrawals.remove(&client_id);
+    state.dispute_counts.remove(&client_id);
+    // Failed transactions might not have created the client
+    if let (Some(client), Some(result_writer)) = (state.clients.remove(&client_id), writer.as_mut())
+    {
+        let result = write_result_record(
+            result_writer,
+            client_id,
+            &client,
+            disputed_funds,
+            result_options,
+        );
+        close_on_broken_pipe(result, writer)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the transactions from several readers one after the other, as if they were a single
+/// input: transactions and clients persist across readers, so a transaction of a reader can be
+/// disputed in a later one.
+#[cfg(feature = "cli")]
+pub(crate) fn process_chained_transactions_into<R: Read, L: Write>(
+    readers: Vec<R>,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    mut log: L,
+) -> Result<(), Error> {
+    for reader in readers {
+        process_transactions_into(reader, state, options, &mut log)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the transactions of several tenants, one reader per tenant, and processes each of them
+/// into its own state. Tenants share the processing options and the transaction IDs, which are
+/// global: a tenant cannot reuse the ID of a transaction of another tenant. Clients are isolated
+/// and disputes can only target transactions of the same tenant.
+/// Transactions are stored in a store of the given kind; stores on disk are in a subdirectory of
+/// the given path named after the tenant's file number.
+#[cfg(feature = "cli")]
+pub(crate) fn process_namespaced_transactions<R: Read, L: Write>(
+    readers: Vec<R>,
+    store_kind: TransactionStoreKind,
+    store_path: Option<&Path>,
+    options: &mut ProcessingOptions,
+    mut log: L,
+) -> Result<Vec<ProcessingState>, Error> {
+    let mut states = Vec::new();
+    let mut other_tenants_transaction_ids = HashSet::new();
+    for (index, reader) in readers.into_iter().enumerate() {
+        let mut state = ProcessingState {
+            other_tenants_transaction_ids,
+            ..Default::default()
+        };
+        let tenant_store_path = store_path.map(|path| path.join((index + 1).to_string()));
+        select_transaction_store(&mut state, store_kind, tenant_store_path.as_deref())?;
+        process_transactions_into(reader, &mut state, options, &mut log)?;
+        // The IDs are handed over to the next tenant, along with the ones of this tenant
+        other_tenants_transaction_ids = mem::take(&mut state.other_tenants_transaction_ids);
+        other_tenants_transaction_ids.extend(
+            state
+                .transactions
+                .entries()?
+                .into_iter()
+                .map(|(transaction_id, _)| transaction_id),
+        );
+        other_tenants_transaction_ids.extend(&state.unstored_transaction_ids);
+        states.push(state);
+    }
+
+    Ok(states)
+}
+
+/// Processes already parsed records into an existing state.
+/// Only used by tests for now; the application always reads records from CSV.
+#[cfg(test)]
+pub(crate) fn process_records<I: IntoIterator<Item = TransactionRecord>, L: Write>(
+    records: I,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    mut log: L,
+) -> Result<(), Error> {
+    for record in records {
+        process_record(record, state, options, &mut log)?;
+    }
+
+    Ok(())
+}
+
+/// Processes transactions from a reader that is still being written to, like a growing file.
+/// Only complete lines are processed: a partial last line is kept until the rest of it is
+/// available. Records are therefore expected to fit on a single line.
+#[cfg(feature = "cli")]
+pub(crate) struct Follower<R> {
+    /// The followed reader.
+    reader: R,
+    /// Data read after the last complete line.
+    pending: Vec<u8>,
+    /// Number of bytes of the complete lines processed so far, to locate errors in the reader.
+    offset: u64,
+    /// The header, once its line has been read.
+    headers: Option<csv::StringRecord>,
+    /// The state resulting from the transactions processed so far.
+    pub(crate) state: ProcessingState,
+}
+
+#[cfg(feature = "cli")]
+impl<R: Read> Follower<R> {
+    /// Creates a follower processing transactions into the given state.
+    pub(crate) fn new(reader: R, state: ProcessingState) -> Self {
+        Self {
+            reader,
+            pending: Vec::new(),
+            offset: 0,
+            headers: None,
+            state,
+        }
+    }
+
+    /// Reads all the data currently available and processes the complete lines.
+    pub(crate) fn poll<L: Write>(
+        &mut self,
+        options: &mut ProcessingOptions,
+        mut log: L,
+    ) -> Result<(), Error> {
+        self.reader
+            .read_to_end(&mut self.pending)
+            .map_err(Error::ReadError)?;
+        let Some(end) = self.pending.iter().rposition(|&byte| byte == b'\n') else {
+            return Ok(());
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+        // The header is read as a record, so both are trimmed here as needed
+        let mut reader = transaction_reader_builder(TrimMode::None)
+            .has_headers(false)
+            .from_reader(lines.as_slice());
+
+        let offset = self.offset;
+        self.offset += lines.len() as u64;
+        for record in reader.records() {
+            // Positions are relative to the lines read by this poll
+            let mut record = record.map_err(|err| match Error::from_transaction_parsing(err) {
+                Error::InvalidUtf8(position) => Error::InvalidUtf8(offset + position),
+                err => err,
+            })?;
+            let Some(headers) = &self.headers else {
+                if options.trim.trims_headers() {
+                    record.trim();
+                }
+                self.headers = Some(record);
+                continue;
+            };
+            if options.trim.trims_fields() {
+                record.trim();
+            }
+            process_csv_record(&record, headers, &mut self.state, options, &mut log)?;
+        }
+
+        Ok(())
+    }
+}
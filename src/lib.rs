@@ -0,0 +1,45 @@
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![deny(clippy::panic)]
+#![allow(clippy::enum_variant_names)] // Allow "Error" suffix in the Error enum
+
+//! Processes a list of transactions and outputs the final state of clients.
+//!
+//! Records can be fed one at a time to a [`PaymentEngine`], or read from CSV with
+//! [`process_transactions`]. The command line application is in the `cli` module, behind the
+//! default `cli` feature.
+
+#[cfg(feature = "cli")]
+pub mod cli;
+mod engine;
+mod error;
+mod input;
+mod output;
+mod processing;
+mod state;
+mod store;
+#[cfg(test)]
+mod tests;
+mod transaction;
+mod types;
+
+pub use engine::{PaymentEngine, ProcessingResult};
+pub use error::Error;
+pub use input::process_transactions;
+pub use output::{write_result, write_result_json, ResultOptions};
+pub use state::ProcessingStats;
+pub use transaction::TransactionRecord;
+pub use types::{Client, ClientId, MoneyAmount, TransactionId};
+
+use std::collections::HashMap;
+
+/// The map used internally for stored transactions and per-client limits, hashed with the
+/// randomly seeded SipHash. Public signatures always use the standard `HashMap`.
+#[cfg(not(feature = "fxhash"))]
+type InternalHashMap<K, V> = HashMap<K, V>;
+
+/// The map used internally for stored transactions and per-client limits, hashed with the
+/// deterministic and faster `FxHasher` of the `fxhash` feature. It does not protect against
+/// HashDoS, so inputs should be trusted.
+#[cfg(feature = "fxhash")]
+type InternalHashMap<K, V> = HashMap<K, V, rustc_hash::FxBuildHasher>;
@@ -0,0 +1,495 @@
+//! Writing the accounts of the clients as CSV or JSON, and reading them back.
+
+use crate::{
+    error::Error,
+    processing::add_total,
+    types::{Client, ClientId, MoneyAmount, DECIMAL_PRECISION},
+};
+#[cfg(feature = "cli")]
+use crate::{
+    processing::{add_funds, sub_funds},
+    state::ProcessingState,
+};
+#[cfg(feature = "cli")]
+use csv::Trim;
+#[cfg(feature = "cli")]
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io::Write};
+#[cfg(feature = "cli")]
+use std::{fs, io::Read, path::Path};
+
+/// Names of the result columns; they must match the serialized fields of `AccountRecord`.
+pub(crate) const RESULT_HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Name of the optional result column holding the funds under an open dispute.
+const DISPUTED_HEADER: &str = "disputed";
+
+/// An entry in the result, written by `write_result` and read back by `merge_result`.
+/// Client IDs are namespaced by input file when processing with `--namespace-by-file`.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct AccountRecord<C = ClientId> {
+    /// The client ID.
+    #[serde(rename = "client")]
+    client_id: C,
+    /// Available funds.
+    #[serde(rename = "available")]
+    available_funds: MoneyAmount,
+    /// Held funds.
+    #[serde(rename = "held")]
+    held_funds: MoneyAmount,
+    /// Sum of available and held funds.
+    #[serde(rename = "total")]
+    total_funds: MoneyAmount,
+    /// Is this account locked?
+    #[serde(rename = "locked")]
+    is_locked: LockedFlag,
+    /// Sum of the amounts of the transactions under an open dispute, if requested.
+    /// This should always be equal to the held funds minus the pending held deposits.
+    #[serde(rename = "disputed", default, skip_serializing_if = "Option::is_none")]
+    disputed_funds: Option<MoneyAmount>,
+}
+
+impl<C> AccountRecord<C> {
+    /// Creates a result entry for a client, rounding the amounts to the output precision.
+    pub(crate) fn new(client_id: C, client: &Client) -> Self {
+        Self {
+            client_id,
+            available_funds: MoneyAmount(client.available_funds.round_dp(DECIMAL_PRECISION)),
+            held_funds: MoneyAmount(client.held_funds.round_dp(DECIMAL_PRECISION)),
+            total_funds: MoneyAmount(client.total_funds().round_dp(DECIMAL_PRECISION)),
+            is_locked: LockedFlag::Bool(client.is_locked),
+            disputed_funds: None,
+        }
+    }
+
+    /// Creates a result entry for a client as configured by the result options.
+    /// The funds under an open dispute are included if given.
+    fn with_options(
+        client_id: C,
+        client: &Client,
+        disputed_funds: Option<MoneyAmount>,
+        options: &ResultOptions,
+    ) -> Self {
+        let mut record = Self::new(client_id, client);
+        if options.bool_as_int {
+            record.is_locked = LockedFlag::Int(u8::from(client.is_locked));
+        }
+        if options.consistent_totals {
+            record.total_funds = record.available_funds;
+            add_total(&mut record.total_funds, record.held_funds);
+        }
+        record.disputed_funds = disputed_funds
+            .map(|disputed_funds| MoneyAmount(disputed_funds.round_dp(DECIMAL_PRECISION)));
+        record
+    }
+}
+
+/// A client ID prefixed with the number of the input file it comes from, starting at 1.
+/// Clients with the same ID in different files are different clients.
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+#[display("{file_number}:{client_id}")]
+pub(crate) struct NamespacedClientId {
+    /// The number of the input file.
+    pub(crate) file_number: usize,
+    /// The client ID within the input file.
+    pub(crate) client_id: ClientId,
+}
+
+#[cfg(feature = "cli")]
+impl Serialize for NamespacedClientId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The locked flag of a result entry, which can be rendered as a boolean or as a number.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+enum LockedFlag {
+    /// Rendered as `true` or `false`.
+    Bool(bool),
+    /// Rendered as `1` or `0`.
+    Int(u8),
+}
+
+impl LockedFlag {
+    #[cfg(feature = "cli")]
+    fn is_locked(self) -> bool {
+        match self {
+            LockedFlag::Bool(is_locked) => is_locked,
+            LockedFlag::Int(is_locked) => is_locked != 0,
+        }
+    }
+}
+
+/// The format of the result.
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub(crate) enum ResultFormat {
+    /// CSV with a header.
+    #[default]
+    Csv,
+
+    /// A JSON array of objects, one per client.
+    Json,
+}
+
+/// Options controlling how the result is written.
+#[derive(Debug, Default)]
+pub struct ResultOptions {
+    /// Render the locked flag as `1` or `0` instead of `true` or `false`.
+    pub bool_as_int: bool,
+    /// Write the sum of the rounded available and held funds as the total, instead of the rounded
+    /// total, so that the columns always add up.
+    pub consistent_totals: bool,
+}
+
+/// Writes the client's account status to a writer.
+/// If the funds under an open dispute are given, they are written in an additional column.
+/// The reader of the result may stop reading early, like `head` in a pipeline: a broken pipe is
+/// not an error, the rest of the result is simply not written.
+pub fn write_result<W: Write>(
+    clients: &HashMap<ClientId, Client>,
+    disputed_funds: Option<&HashMap<ClientId, MoneyAmount>>,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    match write_accounts(clients, disputed_funds, options, writer) {
+        Err(err) if err.is_broken_pipe() => Ok(()),
+        result => result,
+    }
+}
+
+/// Writes the client's account status to a writer, see `write_result`.
+fn write_accounts<W: Write>(
+    clients: &HashMap<ClientId, Client>,
+    disputed_funds: Option<&HashMap<ClientId, MoneyAmount>>,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    write_result_header(&mut writer, disputed_funds.is_some())?;
+
+    // Clients are sorted so that results can be compared
+    let mut clients: Vec<_> = clients.iter().collect();
+    clients.sort_by_key(|(id, _)| **id);
+    for (id, client) in clients {
+        let client_disputed_funds = disputed_funds
+            .map(|disputed_funds| disputed_funds.get(id).copied().unwrap_or_default());
+        write_result_record(&mut writer, *id, client, client_disputed_funds, options)?;
+    }
+
+    writer.flush().map_err(Error::FlushError)?;
+
+    Ok(())
+}
+
+/// Writes the client's account status to a writer, as JSON: an array of objects with the same
+/// fields and rounding as the CSV result, sorted by client ID. Like for `write_result`, a broken
+/// pipe is not an error.
+pub fn write_result_json<W: Write>(
+    clients: &HashMap<ClientId, Client>,
+    disputed_funds: Option<&HashMap<ClientId, MoneyAmount>>,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    match write_accounts_json(clients, disputed_funds, options, writer) {
+        Err(err) if err.is_broken_pipe() => Ok(()),
+        result => result,
+    }
+}
+
+/// Writes the client's account status to a writer as JSON, see `write_result_json`.
+fn write_accounts_json<W: Write>(
+    clients: &HashMap<ClientId, Client>,
+    disputed_funds: Option<&HashMap<ClientId, MoneyAmount>>,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    let mut clients: Vec<_> = clients.iter().collect();
+    clients.sort_by_key(|(id, _)| **id);
+    let records: Vec<_> = clients
+        .into_iter()
+        .map(|(id, client)| {
+            let client_disputed_funds = disputed_funds
+                .map(|disputed_funds| disputed_funds.get(id).copied().unwrap_or_default());
+            AccountRecord::with_options(*id, client, client_disputed_funds, options)
+        })
+        .collect();
+    write_json_records(&records, writer)
+}
+
+/// Writes result entries to a writer as a JSON array.
+fn write_json_records<W: Write, C: Serialize>(
+    records: &[AccountRecord<C>],
+    mut writer: W,
+) -> Result<(), Error> {
+    serde_json::to_writer_pretty(&mut writer, records).map_err(Error::JsonError)?;
+    writeln!(writer).map_err(Error::OutputWriteError)?;
+    writer.flush().map_err(Error::FlushError)?;
+
+    Ok(())
+}
+
+/// Writes the client's account status in the given format, see `write_result`.
+#[cfg(feature = "cli")]
+pub(crate) fn write_formatted_result<W: Write>(
+    format: ResultFormat,
+    clients: &HashMap<ClientId, Client>,
+    disputed_funds: Option<&HashMap<ClientId, MoneyAmount>>,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    match format {
+        ResultFormat::Csv => write_result(clients, disputed_funds, options, writer),
+        ResultFormat::Json => write_result_json(clients, disputed_funds, options, writer),
+    }
+}
+
+/// Writes the clients of several tenants in the given format, prefixing client IDs with the
+/// tenant's file number. Like for `write_result`, a broken pipe is not an error.
+#[cfg(feature = "cli")]
+pub(crate) fn write_namespaced_result<W: Write>(
+    states: &[ProcessingState],
+    with_disputed_funds: bool,
+    format: ResultFormat,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    match write_namespaced_accounts(states, with_disputed_funds, format, options, writer) {
+        Err(err) if err.is_broken_pipe() => Ok(()),
+        result => result,
+    }
+}
+
+/// Writes the clients of several tenants, see `write_namespaced_result`.
+#[cfg(feature = "cli")]
+fn write_namespaced_accounts<W: Write>(
+    states: &[ProcessingState],
+    with_disputed_funds: bool,
+    format: ResultFormat,
+    options: &ResultOptions,
+    writer: W,
+) -> Result<(), Error> {
+    let mut records = Vec::new();
+    for (index, state) in states.iter().enumerate() {
+        let disputed_funds = with_disputed_funds
+            .then(|| state.disputed_funds())
+            .transpose()?;
+        let mut clients: Vec<_> = state.clients.iter().collect();
+        clients.sort_by_key(|(id, _)| **id);
+        for (id, client) in clients {
+            let client_id = NamespacedClientId {
+                file_number: index + 1,
+                client_id: *id,
+            };
+            let client_disputed_funds = disputed_funds
+                .as_ref()
+                .map(|disputed_funds| disputed_funds.get(id).copied().unwrap_or_default());
+            records.push(AccountRecord::with_options(
+                client_id,
+                client,
+                client_disputed_funds,
+                options,
+            ));
+        }
+    }
+
+    match format {
+        ResultFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(writer);
+            write_result_header(&mut writer, with_disputed_funds)?;
+            for record in records {
+                writer
+                    .serialize(record)
+                    .map_err(Error::SerializationError)?;
+            }
+            writer.flush().map_err(Error::FlushError)
+        }
+        ResultFormat::Json => write_json_records(&records, writer),
+    }
+}
+
+/// Writes the header of the result.
+/// The header is written explicitly rather than derived from the first serialized record so that
+/// it is also present when there are no clients.
+pub(crate) fn write_result_header<W: Write>(
+    writer: &mut csv::Writer<W>,
+    with_disputed_funds: bool,
+) -> Result<(), Error> {
+    let disputed_header = with_disputed_funds.then_some(DISPUTED_HEADER);
+    writer
+        .write_record(RESULT_HEADER.into_iter().chain(disputed_header))
+        .map_err(Error::WriteError)
+}
+
+/// Writes the result entry of a client.
+/// The funds under an open dispute are written in an additional column if given.
+pub(crate) fn write_result_record<W: Write, C: Serialize>(
+    writer: &mut csv::Writer<W>,
+    client_id: C,
+    client: &Client,
+    disputed_funds: Option<MoneyAmount>,
+    options: &ResultOptions,
+) -> Result<(), Error> {
+    let record = AccountRecord::with_options(client_id, client, disputed_funds, options);
+    writer.serialize(record).map_err(Error::SerializationError)
+}
+
+/// Writes the result entry of every client as JSON to a file named after the client ID, in a
+/// directory that is created if needed. Client IDs are numbers, so file names are always safe.
+#[cfg(feature = "cli")]
+pub(crate) fn write_json_dir(
+    clients: &HashMap<ClientId, Client>,
+    directory: &Path,
+    options: &ResultOptions,
+) -> Result<(), Error> {
+    fs::create_dir_all(directory)
+        .map_err(|err| Error::FileWriteError(directory.to_path_buf(), err))?;
+    for (id, client) in clients {
+        let record = AccountRecord::with_options(*id, client, None, options);
+        let json = serde_json::to_string_pretty(&record).map_err(Error::JsonError)?;
+        let path = directory.join(format!("{}.json", id));
+        fs::write(&path, json).map_err(|err| Error::FileWriteError(path, err))?;
+    }
+
+    Ok(())
+}
+
+/// An entry in the delta output: the net change of a client's account.
+#[cfg(feature = "cli")]
+#[derive(Debug, Serialize)]
+struct DeltaRecord<C = ClientId> {
+    /// The client ID.
+    #[serde(rename = "client")]
+    client_id: C,
+    /// Change of the available funds.
+    #[serde(rename = "available")]
+    available_funds: MoneyAmount,
+    /// Change of the held funds.
+    #[serde(rename = "held")]
+    held_funds: MoneyAmount,
+    /// New locked state, only if it changed.
+    #[serde(rename = "locked")]
+    is_locked: Option<bool>,
+}
+
+/// Writes the net change of each client's account between the opening and the closing state.
+/// Clients whose account did not change are not written; new clients start with an empty account.
+/// A change that does not fit in an amount is an error.
+#[cfg(feature = "cli")]
+pub(crate) fn write_deltas<W: Write>(
+    opening_clients: &HashMap<ClientId, Client>,
+    closing_clients: &HashMap<ClientId, Client>,
+    writer: W,
+) -> Result<(), Error> {
+    let mut writer = delta_writer(writer)?;
+    write_delta_records(opening_clients, closing_clients, |id| id, &mut writer)?;
+    writer.flush().map_err(Error::FlushError)?;
+
+    Ok(())
+}
+
+/// Writes the net change of each client's account of several tenants, prefixing client IDs with
+/// the tenant's file number like `write_namespaced_result`. Tenants start without any client.
+#[cfg(feature = "cli")]
+pub(crate) fn write_namespaced_deltas<W: Write>(
+    states: &[ProcessingState],
+    writer: W,
+) -> Result<(), Error> {
+    let mut writer = delta_writer(writer)?;
+    for (index, state) in states.iter().enumerate() {
+        let namespaced_client_id = |client_id| NamespacedClientId {
+            file_number: index + 1,
+            client_id,
+        };
+        write_delta_records(
+            &HashMap::new(),
+            &state.clients,
+            namespaced_client_id,
+            &mut writer,
+        )?;
+    }
+    writer.flush().map_err(Error::FlushError)?;
+
+    Ok(())
+}
+
+/// Returns a CSV writer of the delta output, with its header already written.
+#[cfg(feature = "cli")]
+fn delta_writer<W: Write>(writer: W) -> Result<csv::Writer<W>, Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer
+        .write_record(["client", "available", "held", "locked"])
+        .map_err(Error::WriteError)?;
+    Ok(writer)
+}
+
+/// Writes the net change of each changed client's account, sorted by client ID, with the client
+/// ID returned by `result_client_id`.
+#[cfg(feature = "cli")]
+fn write_delta_records<W: Write, C: Serialize>(
+    opening_clients: &HashMap<ClientId, Client>,
+    closing_clients: &HashMap<ClientId, Client>,
+    result_client_id: impl Fn(ClientId) -> C,
+    writer: &mut csv::Writer<W>,
+) -> Result<(), Error> {
+    let mut closing_clients: Vec<_> = closing_clients.iter().collect();
+    closing_clients.sort_by_key(|(id, _)| **id);
+    for (id, closing) in closing_clients {
+        let opening = opening_clients.get(id).cloned().unwrap_or_default();
+        if &opening == closing {
+            continue;
+        }
+        let available_funds = sub_funds(closing.available_funds, opening.available_funds, *id)?;
+        let held_funds = sub_funds(closing.held_funds, opening.held_funds, *id)?;
+        writer
+            .serialize(DeltaRecord {
+                client_id: result_client_id(*id),
+                available_funds: MoneyAmount(available_funds.round_dp(DECIMAL_PRECISION)),
+                held_funds: MoneyAmount(held_funds.round_dp(DECIMAL_PRECISION)),
+                is_locked: (opening.is_locked != closing.is_locked).then_some(closing.is_locked),
+            })
+            .map_err(Error::SerializationError)?;
+    }
+
+    Ok(())
+}
+
+/// Reads a result previously written by `write_result` and merges it into a map of clients.
+/// This allows combining the results of shards processed separately. Clients are not expected to
+/// span several shards, but if they do, their balances are summed and the account is considered
+/// locked if it is locked in any of them.
+#[cfg(feature = "cli")]
+pub(crate) fn merge_result<R: Read>(
+    reader: R,
+    clients: &mut HashMap<ClientId, Client>,
+) -> Result<(), Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+
+    for record in reader.deserialize() {
+        let account: AccountRecord = record.map_err(Error::ParsingError)?;
+        // The total is ignored, as it is always the sum of available and held funds
+        let client = clients.entry(account.client_id).or_default();
+        client.available_funds = add_funds(
+            client.available_funds,
+            account.available_funds,
+            account.client_id,
+        )?;
+        client.held_funds = add_funds(client.held_funds, account.held_funds, account.client_id)?;
+        client.is_locked |= account.is_locked.is_locked();
+    }
+
+    Ok(())
+}
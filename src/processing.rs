@@ -0,0 +1,690 @@
+//! Processing of a single transaction record, with a handler per transaction type.
+
+use crate::{
+    error::Error,
+    input::{RejectedRecordWriter, TrimMode},
+    state::ProcessingState,
+    store::TransactionStore,
+    transaction::{
+        DisputedState, SettlementState, Transaction, TransactionKind, TransactionRecord,
+    },
+    types::{Client, ClientId, Clock, MoneyAmount, TransactionId, DECIMAL_PRECISION},
+};
+#[cfg(feature = "cli")]
+use std::collections::HashMap;
+use std::io::Write;
+
+/// A custom validation hook, called for every record before it is processed.
+/// Returning an error rejects the record.
+type RecordValidator = Box<dyn FnMut(&TransactionRecord) -> Result<(), Error>>;
+
+/// Options controlling how transactions are processed.
+#[derive(Default)]
+pub(crate) struct ProcessingOptions {
+    /// Write every successfully parsed record to the log before processing it.
+    pub(crate) echo_input: bool,
+    /// Optional validator enforcing domain-specific rules on top of the built-in checks.
+    pub(crate) validator: Option<RecordValidator>,
+    /// Number of most recent deposits per client that are not available for withdrawal yet.
+    pub(crate) hold_new_deposits: usize,
+    /// Reject disputes and do not store transactions; only their IDs are kept, as they are unique.
+    pub(crate) no_disputes: bool,
+    /// Maximum amount each client can withdraw per UTC day.
+    pub(crate) daily_withdrawal_limit: Option<MoneyAmount>,
+    /// Allow resolving a charged back transaction to unlock the client account.
+    pub(crate) allow_resolve_after_chargeback: bool,
+    /// Reject records that do not have every column of the header.
+    pub(crate) strict_columns: bool,
+    /// Count the transactions rejected because the client account is locked in the report instead
+    /// of logging them.
+    pub(crate) tally_locked_rejections: bool,
+    /// How much is written to the log about transaction errors.
+    pub(crate) error_verbosity: ErrorVerbosity,
+    /// Where to write the CSV records that have not been applied.
+    pub(crate) rejected_records: Option<RejectedRecordWriter>,
+    /// Write a warning to the log for amounts that have more decimal places than the output.
+    pub(crate) warn_precision_loss: bool,
+    /// Maximum number of disputes each client can open.
+    pub(crate) max_disputes_per_client: Option<usize>,
+    /// Maximum number of disputes that can be open at the same time across all clients.
+    pub(crate) max_open_disputes: Option<usize>,
+    /// Which parts of the input have their surrounding spaces and tabs trimmed.
+    pub(crate) trim: TrimMode,
+    /// Clock used to reject the transactions dated after the current time, if they are rejected.
+    pub(crate) reject_future_timestamps: Option<Box<dyn Clock>>,
+}
+
+#[cfg(feature = "cli")]
+impl ProcessingOptions {
+    /// Flushes the rejected records, if they are written.
+    pub(crate) fn flush_rejected_records(&mut self) -> Result<(), Error> {
+        match &mut self.rejected_records {
+            Some(rejected_records) => rejected_records.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How much is written to the log about transaction errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum ErrorVerbosity {
+    /// Only count the errors in the report.
+    #[cfg(feature = "cli")]
+    Quiet,
+
+    /// Write a line for each error.
+    #[default]
+    Normal,
+
+    /// Write a line for each error, including the record that caused it.
+    Verbose,
+}
+
+/// Returns the funds of a client increased by an amount, or an error if they would overflow.
+pub(crate) fn add_funds(
+    funds: MoneyAmount,
+    amount: MoneyAmount,
+    client_id: ClientId,
+) -> Result<MoneyAmount, Error> {
+    funds
+        .checked_add(*amount)
+        .map(MoneyAmount)
+        .ok_or(Error::BalanceOverflow(client_id))
+}
+
+/// Returns the funds of a client decreased by an amount, or an error if they would overflow.
+pub(crate) fn sub_funds(
+    funds: MoneyAmount,
+    amount: MoneyAmount,
+    client_id: ClientId,
+) -> Result<MoneyAmount, Error> {
+    funds
+        .checked_sub(*amount)
+        .map(MoneyAmount)
+        .ok_or(Error::BalanceOverflow(client_id))
+}
+
+/// Adds an amount to a total of the report. Totals saturate instead of overflowing, as they are
+/// only used to audit the funds.
+pub(crate) fn add_total(total: &mut MoneyAmount, amount: MoneyAmount) {
+    *total = MoneyAmount(total.saturating_add(*amount));
+}
+
+/// Sums amounts, saturating instead of overflowing like the report totals.
+pub(crate) fn sum_amounts<I: IntoIterator<Item = MoneyAmount>>(amounts: I) -> MoneyAmount {
+    let mut total = MoneyAmount::default();
+    for amount in amounts {
+        add_total(&mut total, amount);
+    }
+    total
+}
+
+/// Sums the total funds of clients. The sum saturates instead of overflowing, like the report
+/// totals it is compared with.
+#[cfg(feature = "cli")]
+pub(crate) fn sum_total_funds(clients: &HashMap<ClientId, Client>) -> MoneyAmount {
+    sum_amounts(clients.values().map(Client::total_funds))
+}
+
+/// Process a deposit.
+fn process_deposit(
+    client: &mut Client,
+    client_id: ClientId,
+    amount: Option<MoneyAmount>,
+) -> Result<(), Error> {
+    let Some(amount) = amount else {
+        return Err(Error::DepositWithoutAmount);
+    };
+
+    let available_funds = add_funds(client.available_funds, amount, client_id)?;
+    // The total funds must not overflow either
+    add_funds(available_funds, client.held_funds, client_id)?;
+    client.available_funds = available_funds;
+
+    Ok(())
+}
+
+/// Process a withdrawal.
+/// Immature funds are part of the available funds but cannot be withdrawn yet.
+fn process_withdrawal(
+    client: &mut Client,
+    client_id: ClientId,
+    amount: Option<MoneyAmount>,
+    immature_funds: MoneyAmount,
+) -> Result<(), Error> {
+    let Some(amount) = amount else {
+        return Err(Error::WithdrawalWithoutAmount);
+    };
+
+    let withdrawable_funds = sub_funds(client.available_funds, immature_funds, client_id)?;
+    if withdrawable_funds < amount {
+        return Err(Error::NotEnoughAvailableFunds(
+            client_id,
+            amount,
+            withdrawable_funds,
+        ));
+    }
+
+    client.available_funds = sub_funds(client.available_funds, amount, client_id)?;
+
+    Ok(())
+}
+
+/// Process a held deposit: the funds are credited to the held funds until the deposit settles.
+fn process_hold(
+    client: &mut Client,
+    client_id: ClientId,
+    amount: Option<MoneyAmount>,
+) -> Result<(), Error> {
+    let Some(amount) = amount else {
+        return Err(Error::DepositWithoutAmount);
+    };
+
+    let held_funds = add_funds(client.held_funds, amount, client_id)?;
+    // The total funds must not overflow either
+    add_funds(client.available_funds, held_funds, client_id)?;
+    client.held_funds = held_funds;
+
+    Ok(())
+}
+
+/// Process a settle: the held funds of a pending deposit become available.
+fn process_settle(
+    client: &mut Client,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transactions: &mut dyn TransactionStore,
+) -> Result<(), Error> {
+    let mut target_transaction = target_transaction(transactions, transaction_id, client_id)?;
+
+    if target_transaction.settlement != SettlementState::Pending {
+        return Err(Error::TransactionNotPending(transaction_id));
+    }
+
+    let held_funds = sub_funds(client.held_funds, target_transaction.amount, client_id)?;
+    let available_funds = add_funds(client.available_funds, target_transaction.amount, client_id)?;
+    target_transaction.settlement = SettlementState::Settled;
+    transactions.insert(transaction_id, target_transaction)?;
+    client.available_funds = available_funds;
+    client.held_funds = held_funds;
+
+    Ok(())
+}
+
+/// Process a void: a pending deposit is cancelled and its held funds are removed entirely.
+/// A voided deposit cannot be settled or disputed.
+fn process_void(
+    client: &mut Client,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transactions: &mut dyn TransactionStore,
+) -> Result<(), Error> {
+    let mut target_transaction = target_transaction(transactions, transaction_id, client_id)?;
+
+    if target_transaction.settlement != SettlementState::Pending {
+        return Err(Error::TransactionNotPending(transaction_id));
+    }
+
+    let held_funds = sub_funds(client.held_funds, target_transaction.amount, client_id)?;
+    target_transaction.settlement = SettlementState::Voided;
+    transactions.insert(transaction_id, target_transaction)?;
+    client.held_funds = held_funds;
+
+    Ok(())
+}
+
+/// Returns the transaction targeted by a dispute, a resolve or a chargeback of a client.
+/// Clients can only target their own transactions.
+fn target_transaction(
+    transactions: &dyn TransactionStore,
+    transaction_id: TransactionId,
+    client_id: ClientId,
+) -> Result<Transaction, Error> {
+    let Some(target_transaction) = transactions.get(transaction_id)? else {
+        return Err(Error::UnknownTransactionId(transaction_id));
+    };
+
+    if target_transaction.client_id != client_id {
+        return Err(Error::TransactionClientMismatch(transaction_id, client_id));
+    }
+
+    Ok(target_transaction)
+}
+
+/// Process a dispute.
+/// A dispute with an amount only disputes that part of the target transaction; the remainder is
+/// left untouched by the dispute and by its resolve or chargeback, and cannot be disputed later.
+/// Disputing a deposit holds the disputed funds, which are no longer available. Disputing a
+/// withdrawal claims that the money left the account wrongly: the disputed funds are held as a
+/// pending credit, leaving the available funds unchanged.
+fn process_dispute(
+    client: &mut Client,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Option<MoneyAmount>,
+    transactions: &mut dyn TransactionStore,
+) -> Result<(), Error> {
+    let mut target_transaction = target_transaction(transactions, transaction_id, client_id)?;
+
+    match target_transaction.settlement {
+        SettlementState::Settled => {}
+        SettlementState::Pending => return Err(Error::TransactionPending(transaction_id)),
+        SettlementState::Voided => return Err(Error::TransactionVoided(transaction_id)),
+    }
+    if target_transaction.disputed != DisputedState::NotDisputed {
+        return Err(Error::TransactionAlreadyUnderDispute(transaction_id));
+    }
+
+    let disputed_amount = amount.unwrap_or(target_transaction.amount);
+    if disputed_amount > target_transaction.amount {
+        return Err(Error::DisputedAmountTooLarge(
+            transaction_id,
+            disputed_amount,
+        ));
+    }
+
+    let held_funds = add_funds(client.held_funds, disputed_amount, client_id)?;
+    let available_funds = if target_transaction.kind == TransactionKind::Deposit {
+        sub_funds(client.available_funds, disputed_amount, client_id)?
+    } else {
+        // The total funds must not overflow either
+        add_funds(client.available_funds, held_funds, client_id)?;
+        client.available_funds
+    };
+    target_transaction.disputed = DisputedState::Disputed;
+    target_transaction.disputed_amount = disputed_amount;
+    transactions.insert(transaction_id, target_transaction)?;
+    client.available_funds = available_funds;
+    client.held_funds = held_funds;
+
+    Ok(())
+}
+
+/// Process a resolve.
+/// The held funds are released to the available funds, so a resolved withdrawal dispute credits
+/// the disputed funds back to the client.
+/// Resolving a charged back transaction, if allowed, unlocks the client account as part of an
+/// unfreeze workflow; the funds removed by the chargeback are not restored.
+fn process_resolve(
+    client: &mut Client,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transactions: &mut dyn TransactionStore,
+    allow_after_chargeback: bool,
+) -> Result<(), Error> {
+    let mut target_transaction = target_transaction(transactions, transaction_id, client_id)?;
+
+    match target_transaction.disputed {
+        DisputedState::Disputed => {}
+        DisputedState::ChargedBack if allow_after_chargeback => {
+            target_transaction.disputed = DisputedState::Resolved;
+            transactions.insert(transaction_id, target_transaction)?;
+            client.is_locked = false;
+            return Ok(());
+        }
+        DisputedState::ChargedBack => return Err(Error::CannotResolveChargedBack(transaction_id)),
+        _ => return Err(Error::TransactionNotUnderDispute(transaction_id)),
+    }
+
+    let held_funds = sub_funds(
+        client.held_funds,
+        target_transaction.disputed_amount,
+        client_id,
+    )?;
+    let available_funds = add_funds(
+        client.available_funds,
+        target_transaction.disputed_amount,
+        client_id,
+    )?;
+    target_transaction.disputed = DisputedState::Resolved;
+    transactions.insert(transaction_id, target_transaction)?;
+    client.available_funds = available_funds;
+    client.held_funds = held_funds;
+
+    Ok(())
+}
+
+/// Process a chargeback.
+/// Only the disputed part of the target transaction is removed from the held funds. A charged back
+/// deposit is reversed: the held funds are withdrawn. A charged back withdrawal is reversed too:
+/// the held funds are credited back to the available funds. In both cases the account is locked.
+pub(crate) fn process_chargeback(
+    client: &mut Client,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transactions: &mut dyn TransactionStore,
+) -> Result<(), Error> {
+    let mut target_transaction = target_transaction(transactions, transaction_id, client_id)?;
+
+    if target_transaction.disputed != DisputedState::Disputed {
+        return Err(Error::TransactionNotUnderDispute(transaction_id));
+    }
+
+    let held_funds = sub_funds(
+        client.held_funds,
+        target_transaction.disputed_amount,
+        client_id,
+    )?;
+    let available_funds = if target_transaction.kind == TransactionKind::Withdrawal {
+        add_funds(
+            client.available_funds,
+            target_transaction.disputed_amount,
+            client_id,
+        )?
+    } else {
+        client.available_funds
+    };
+    target_transaction.disputed = DisputedState::ChargedBack;
+    transactions.insert(transaction_id, target_transaction)?;
+    client.available_funds = available_funds;
+    client.held_funds = held_funds;
+    client.is_locked = true;
+
+    Ok(())
+}
+
+/// Process a transaction.
+pub(crate) fn process_transaction(
+    record: TransactionRecord,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+) -> Result<(), Error> {
+    if let Some(amount) = record.amount {
+        if amount.is_sign_negative() || amount.is_zero() {
+            return Err(Error::InvalidAmount(amount));
+        }
+    }
+    if let (Some(clock), Some(timestamp)) = (&options.reject_future_timestamps, record.timestamp) {
+        if timestamp > clock.now() {
+            return Err(Error::FutureTimestamp(record.id));
+        }
+    }
+    if let Some(validator) = &mut options.validator {
+        validator(&record)?;
+    }
+    // Transaction IDs are unique: a deposit or a withdrawal reusing the ID of a stored transaction
+    // is rejected, leaving the stored one untouched
+    if matches!(
+        record.type_string.as_str(),
+        "deposit" | "hold" | "withdrawal"
+    ) && (state.transactions.get(record.id)?.is_some()
+        || state.unstored_transaction_ids.contains(&record.id)
+        || state.other_tenants_transaction_ids.contains(&record.id))
+    {
+        return Err(Error::DuplicateTransactionId(record.id));
+    }
+    // Return a client for this id; create a new one if none is found
+    // We assume clients start with an empty account
+    let client = state.clients.entry(record.client_id).or_default();
+    // Refuse to process transactions for locked client accounts, except for resolves targeting a
+    // charged back transaction, which are part of the unfreeze workflow
+    let resolves_chargeback = record.type_string == "resolve"
+        && state
+            .transactions
+            .get(record.id)?
+            .is_some_and(|transaction| transaction.disputed == DisputedState::ChargedBack);
+    if client.is_locked && !resolves_chargeback {
+        return Err(Error::ClientLocked(record.client_id));
+    }
+    // Note that we only store deposits and withdrawals, as other transaction
+    // types don't need to be stored and are processed on the fly
+    match record.type_string.as_str() {
+        // A deposit; a credit to the client's asset account
+        "deposit" => {
+            process_deposit(client, record.client_id, record.amount)?;
+            add_total(
+                &mut state.report.deposited_funds,
+                record.amount.unwrap_or_default(),
+            );
+            if options.hold_new_deposits > 0 {
+                // Hold this deposit; the oldest held one matures if there are too many
+                let recent_deposits = state.recent_deposits.entry(record.client_id).or_default();
+                recent_deposits.extend(record.amount);
+                if recent_deposits.len() > options.hold_new_deposits {
+                    recent_deposits.pop_front();
+                }
+            }
+            // Only store successful deposits; nothing can refer to them when disputes are disabled
+            if options.no_disputes {
+                state.unstored_transaction_ids.insert(record.id);
+            } else {
+                state.transactions.insert(record.id, record.try_into()?)?;
+            }
+        }
+        // A withdrawal; a debit to the client's asset account
+        "withdrawal" => {
+            // Saturating leaves nothing to withdraw, as the immature funds exceed any balance
+            let immature_funds = state
+                .recent_deposits
+                .get(&record.client_id)
+                .map(|recent_deposits| sum_amounts(recent_deposits.iter().copied()))
+                .unwrap_or_default();
+            // Enforce the daily withdrawal limit, which resets at every UTC day boundary
+            let daily_withdrawal = match (options.daily_withdrawal_limit, record.amount) {
+                (Some(limit), Some(amount)) => {
+                    let day = record
+                        .timestamp
+                        .ok_or(Error::MissingTimestamp(record.id))?
+                        .day();
+                    let withdrawn = match state.daily_withdrawals.get(&record.client_id) {
+                        Some(&(withdrawal_day, withdrawn)) if withdrawal_day == day => withdrawn,
+                        _ => MoneyAmount::default(),
+                    };
+                    let withdrawn = add_funds(withdrawn, amount, record.client_id)?;
+                    if withdrawn > limit {
+                        return Err(Error::DailyWithdrawalLimitExceeded(record.client_id, limit));
+                    }
+                    Some((day, withdrawn))
+                }
+                _ => None,
+            };
+            process_withdrawal(client, record.client_id, record.amount, immature_funds)?;
+            add_total(
+                &mut state.report.withdrawn_funds,
+                record.amount.unwrap_or_default(),
+            );
+            if let Some(daily_withdrawal) = daily_withdrawal {
+                state
+                    .daily_withdrawals
+                    .insert(record.client_id, daily_withdrawal);
+            }
+            // Only store successful withdrawals
+            if options.no_disputes {
+                state.unstored_transaction_ids.insert(record.id);
+            } else {
+                state.transactions.insert(record.id, record.try_into()?)?;
+            }
+        }
+        // A held deposit; a credit that only becomes available once settled
+        "hold" => {
+            process_hold(client, record.client_id, record.amount)?;
+            add_total(
+                &mut state.report.deposited_funds,
+                record.amount.unwrap_or_default(),
+            );
+            // Held deposits are always stored, as they are targeted by their settle or void
+            state.transactions.insert(record.id, record.try_into()?)?;
+        }
+        // A settle: the held deposit becomes available
+        "settle" => process_settle(
+            client,
+            record.client_id,
+            record.id,
+            &mut *state.transactions,
+        )?,
+        // A void: the held deposit is cancelled
+        "void" => {
+            process_void(
+                client,
+                record.client_id,
+                record.id,
+                &mut *state.transactions,
+            )?;
+            if let Some(transaction) = state.transactions.get(record.id)? {
+                add_total(&mut state.report.voided_funds, transaction.amount);
+            }
+        }
+        "dispute" | "resolve" | "chargeback" if options.no_disputes => {
+            return Err(Error::DisputesDisabled(record.id))
+        }
+        // A dispute: claim that a transaction was erroneous
+        "dispute" => {
+            // Enforce the maximum number of disputes per client; only successful ones are counted
+            if let Some(max_disputes) = options.max_disputes_per_client {
+                let dispute_count = state.dispute_counts.get(&record.client_id);
+                if dispute_count.is_some_and(|&dispute_count| dispute_count >= max_disputes) {
+                    return Err(Error::TooManyDisputes(record.client_id));
+                }
+            }
+            // Enforce the maximum number of open disputes across all clients
+            if options
+                .max_open_disputes
+                .is_some_and(|max_open_disputes| state.open_disputes >= max_open_disputes)
+            {
+                return Err(Error::TooManyOpenDisputes(record.id));
+            }
+            process_dispute(
+                client,
+                record.client_id,
+                record.id,
+                record.amount,
+                &mut *state.transactions,
+            )?;
+            state.open_disputes += 1;
+            // Disputing a withdrawal credits the client with a pending credit
+            if let Some(transaction) = state.transactions.get(record.id)? {
+                if transaction.kind == TransactionKind::Withdrawal {
+                    add_total(
+                        &mut state.report.disputed_withdrawal_funds,
+                        transaction.disputed_amount,
+                    );
+                }
+            }
+            if options.max_disputes_per_client.is_some() {
+                *state.dispute_counts.entry(record.client_id).or_default() += 1;
+            }
+        }
+        // A resolve: resolution to a dispute
+        "resolve" => {
+            // Resolving a charged back transaction does not close an open dispute
+            let closes_dispute = state
+                .transactions
+                .get(record.id)?
+                .is_some_and(|transaction| transaction.disputed == DisputedState::Disputed);
+            process_resolve(
+                client,
+                record.client_id,
+                record.id,
+                &mut *state.transactions,
+                options.allow_resolve_after_chargeback,
+            )?;
+            if closes_dispute {
+                state.open_disputes = state.open_disputes.saturating_sub(1);
+            }
+        }
+        // A chargeback: client reversing a transaction
+        "chargeback" => {
+            process_chargeback(
+                client,
+                record.client_id,
+                record.id,
+                &mut *state.transactions,
+            )?;
+            state.open_disputes = state.open_disputes.saturating_sub(1);
+            // Charging back a deposit removes the funds from the client
+            if let Some(transaction) = state.transactions.get(record.id)? {
+                if transaction.kind == TransactionKind::Deposit {
+                    add_total(
+                        &mut state.report.charged_back_funds,
+                        transaction.disputed_amount,
+                    );
+                }
+            }
+        }
+        _ => return Err(Error::UnknownTransactionType(record.type_string)),
+    }
+    Ok(())
+}
+
+/// Processes a parsed record, writing it and any processing error to the log.
+/// Returns whether the transaction has been applied.
+pub(crate) fn process_record<L: Write>(
+    record: TransactionRecord,
+    state: &mut ProcessingState,
+    options: &mut ProcessingOptions,
+    log: &mut L,
+) -> Result<bool, Error> {
+    if options.echo_input {
+        writeln!(log, "Parsed record: {:?}", record).map_err(Error::LogWriteError)?;
+    }
+    if options.warn_precision_loss {
+        warn_precision_loss(&record, log)?;
+    }
+    let transaction_id = record.id;
+    let type_string = record.type_string.clone();
+    let raw_record =
+        (options.error_verbosity == ErrorVerbosity::Verbose).then(|| format!("{:?}", record));
+    // Transaction processing errors are not fatal
+    let result = process_transaction(record, state, options);
+    state
+        .report
+        .stats
+        .count(&type_string, transaction_id, &result);
+    match &result {
+        Ok(()) => {}
+        Err(Error::ClientLocked(client_id)) if options.tally_locked_rejections => {
+            *state
+                .report
+                .locked_rejections
+                .entry(*client_id)
+                .or_default() += 1;
+        }
+        Err(err) => log_transaction_error(log, err, raw_record.as_deref(), options)?,
+    }
+    state.update_peak_sizes();
+
+    Ok(result.is_ok())
+}
+
+/// Writes a warning to the log if the amount of a record has more decimal places than the output,
+/// as its precision is lost when balances are rounded.
+pub(crate) fn warn_precision_loss<L: Write>(
+    record: &TransactionRecord,
+    log: &mut L,
+) -> Result<(), Error> {
+    let Some(amount) = record.amount else {
+        return Ok(());
+    };
+    if amount.round_dp(DECIMAL_PRECISION) == *amount {
+        return Ok(());
+    }
+    writeln!(
+        log,
+        "Warning: amount {} of transaction {} has more than {} decimal places; its precision is lost",
+        amount, record.id, DECIMAL_PRECISION
+    )
+    .map_err(Error::LogWriteError)
+}
+
+/// Writes a non-fatal transaction error to the log, depending on the error verbosity. The raw
+/// record is only given with the verbose error verbosity. Errors are counted in the stats.
+pub(crate) fn log_transaction_error<L: Write>(
+    log: &mut L,
+    err: &Error,
+    raw_record: Option<&str>,
+    options: &ProcessingOptions,
+) -> Result<(), Error> {
+    match (options.error_verbosity, raw_record) {
+        #[cfg(feature = "cli")]
+        (ErrorVerbosity::Quiet, _) => Ok(()),
+        (_, Some(raw_record)) => writeln!(
+            log,
+            "Error processing transaction: {} (record: {})",
+            err, raw_record
+        )
+        .map_err(Error::LogWriteError),
+        (_, None) => {
+            writeln!(log, "Error processing transaction: {}", err).map_err(Error::LogWriteError)
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! The processing state: clients, stored transactions and the report of the run.
+
+use crate::{
+    error::Error,
+    store::TransactionStorage,
+    types::{Client, ClientId, MoneyAmount, TransactionId},
+    InternalHashMap,
+};
+#[cfg(feature = "cli")]
+use crate::{
+    processing::{add_funds, add_total, sum_total_funds},
+    transaction::DisputedState,
+};
+#[cfg(feature = "cli")]
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+/// The state of the transaction processing.
+/// It can be written to JSON and read back, for debugging and support. Missing fields are left
+/// empty, so that a settled state only made of clients and transactions can be imported.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub(crate) struct ProcessingState {
+    /// Account data of every client seen so far.
+    pub(crate) clients: HashMap<ClientId, Client>,
+    /// Stored deposits and withdrawals, which can be targeted by other transactions.
+    pub(crate) transactions: TransactionStorage,
+    /// Amounts of the most recent deposits of each client, which have not matured yet.
+    /// Only filled when `ProcessingOptions::hold_new_deposits` is set.
+    pub(crate) recent_deposits: InternalHashMap<ClientId, VecDeque<MoneyAmount>>,
+    /// Day of the last withdrawal of each client and the total withdrawn on that day.
+    /// Only filled when `ProcessingOptions::daily_withdrawal_limit` is set.
+    pub(crate) daily_withdrawals: InternalHashMap<ClientId, (u64, MoneyAmount)>,
+    /// Number of disputes opened by each client.
+    /// Only filled when `ProcessingOptions::max_disputes_per_client` is set.
+    pub(crate) dispute_counts: InternalHashMap<ClientId, usize>,
+    /// Number of disputes that have not been resolved or charged back yet, across all clients.
+    pub(crate) open_disputes: usize,
+    /// IDs of the deposits and withdrawals that have not been stored as disputes are disabled, so
+    /// that they cannot be reused either.
+    /// Only filled when `ProcessingOptions::no_disputes` is set.
+    pub(crate) unstored_transaction_ids: HashSet<TransactionId>,
+    /// IDs of the transactions stored by the tenants processed before this one, which cannot be
+    /// reused as transaction IDs are global. Only filled when processing several tenants.
+    #[serde(skip)]
+    pub(crate) other_tenants_transaction_ids: HashSet<TransactionId>,
+    /// Statistics about the processing.
+    pub(crate) report: ProcessingReport,
+}
+
+/// Statistics about the processing.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct ProcessingReport {
+    /// Number of transactions rejected because the client account is locked, for each client.
+    /// Only filled when `ProcessingOptions::tally_locked_rejections` is set.
+    pub(crate) locked_rejections: InternalHashMap<ClientId, usize>,
+    /// Counts of the processed records, including the rejected ones.
+    pub(crate) stats: ProcessingStats,
+    /// Sum of the total funds of the clients before processing, from opening balances.
+    pub(crate) opening_funds: MoneyAmount,
+    /// Sum of the successful deposits.
+    pub(crate) deposited_funds: MoneyAmount,
+    /// Sum of the successful withdrawals.
+    pub(crate) withdrawn_funds: MoneyAmount,
+    /// Sum of the voided held deposits, removed from the clients.
+    pub(crate) voided_funds: MoneyAmount,
+    /// Sum of the disputed amounts of withdrawals, credited to the clients by their disputes.
+    pub(crate) disputed_withdrawal_funds: MoneyAmount,
+    /// Sum of the disputed amounts of charged back deposits, removed from the clients.
+    pub(crate) charged_back_funds: MoneyAmount,
+    /// Sum of the interest credited to the clients at the end of the run.
+    pub(crate) accrued_interest: MoneyAmount,
+}
+
+/// Counts of the processed records: the applied transactions of each type and the rejected ones.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ProcessingStats {
+    /// Number of records read, whether they have been applied or rejected.
+    pub records: usize,
+    /// Number of applied deposits.
+    pub deposits: usize,
+    /// Number of applied withdrawals.
+    pub withdrawals: usize,
+    /// Number of applied disputes.
+    pub disputes: usize,
+    /// Number of applied resolves.
+    pub resolves: usize,
+    /// Number of applied chargebacks.
+    pub chargebacks: usize,
+    /// Number of applied held deposits.
+    pub holds: usize,
+    /// Number of applied settles.
+    pub settles: usize,
+    /// Number of applied voids.
+    pub voids: usize,
+    /// Number of rejected records for each kind of error, like `UnknownTransactionId`.
+    pub rejections: HashMap<String, usize>,
+    /// ID of the first rejected transaction for each kind of error.
+    pub sample_transaction_ids: HashMap<String, TransactionId>,
+    /// Largest number of stored transactions during the run, to help sizing the memory needed.
+    #[serde(default)]
+    pub peak_transactions: usize,
+    /// Largest number of clients during the run.
+    #[serde(default)]
+    pub peak_clients: usize,
+}
+
+impl ProcessingStats {
+    /// Counts a record of the given type, applied or rejected depending on the result.
+    pub(crate) fn count(
+        &mut self,
+        type_string: &str,
+        transaction_id: TransactionId,
+        result: &Result<(), Error>,
+    ) {
+        self.records += 1;
+        let applied = match (result, type_string) {
+            (Err(err), _) => {
+                let kind = err.kind();
+                self.sample_transaction_ids
+                    .entry(kind.clone())
+                    .or_insert(transaction_id);
+                *self.rejections.entry(kind).or_default() += 1;
+                return;
+            }
+            (Ok(()), "deposit") => &mut self.deposits,
+            (Ok(()), "withdrawal") => &mut self.withdrawals,
+            (Ok(()), "dispute") => &mut self.disputes,
+            (Ok(()), "resolve") => &mut self.resolves,
+            (Ok(()), "chargeback") => &mut self.chargebacks,
+            (Ok(()), "hold") => &mut self.holds,
+            (Ok(()), "settle") => &mut self.settles,
+            (Ok(()), "void") => &mut self.voids,
+            // Unknown types are rejected
+            (Ok(()), _) => return,
+        };
+        *applied += 1;
+    }
+
+    /// Adds the counts of other stats, like those of another tenant processed after these ones.
+    /// The sample transaction IDs of these stats are kept, as they were rejected first. The peak
+    /// sizes are summed, as the tenants are all kept in memory.
+    pub fn merge(&mut self, other: &ProcessingStats) {
+        self.records += other.records;
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.holds += other.holds;
+        self.settles += other.settles;
+        self.voids += other.voids;
+        for (kind, count) in &other.rejections {
+            *self.rejections.entry(kind.clone()).or_default() += count;
+        }
+        for (kind, transaction_id) in &other.sample_transaction_ids {
+            self.sample_transaction_ids
+                .entry(kind.clone())
+                .or_insert(*transaction_id);
+        }
+        self.peak_transactions += other.peak_transactions;
+        self.peak_clients += other.peak_clients;
+    }
+
+    /// Number of rejected records.
+    pub fn rejected(&self) -> usize {
+        self.rejections.values().sum()
+    }
+
+    /// Returns the kinds of errors that rejected records with their counts, the most frequent
+    /// first.
+    pub fn ranked_rejections(&self) -> Vec<(&str, usize)> {
+        let mut rejections: Vec<_> = self
+            .rejections
+            .iter()
+            .map(|(kind, count)| (kind.as_str(), *count))
+            .collect();
+        rejections.sort_by(|(kind, count), (other_kind, other_count)| {
+            other_count.cmp(count).then(kind.cmp(other_kind))
+        });
+        rejections
+    }
+}
+
+/// Summarizes the stats, like `processed 10 records: 3 rejected (2 UnknownTransactionId, 1
+/// ClientLocked)`; the most frequent kinds of errors are listed first.
+impl fmt::Display for ProcessingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "processed {} records: {} rejected",
+            self.records,
+            self.rejected()
+        )?;
+        if self.rejections.is_empty() {
+            return Ok(());
+        }
+        let rejections: Vec<_> = self
+            .ranked_rejections()
+            .into_iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        write!(f, " ({})", rejections.join(", "))
+    }
+}
+
+impl ProcessingReport {
+    /// Sum of the total funds the clients should have according to the processed transactions.
+    /// Like the totals it is computed from, the sum saturates instead of overflowing.
+    #[cfg(feature = "cli")]
+    fn expected_funds(&self) -> MoneyAmount {
+        MoneyAmount(
+            self.opening_funds
+                .saturating_add(*self.deposited_funds)
+                .saturating_sub(*self.withdrawn_funds)
+                .saturating_sub(*self.voided_funds)
+                .saturating_add(*self.disputed_withdrawal_funds)
+                .saturating_sub(*self.charged_back_funds)
+                .saturating_add(*self.accrued_interest),
+        )
+    }
+}
+
+impl ProcessingState {
+    /// Updates the peak sizes of the stats with the current number of stored transactions and
+    /// clients.
+    pub(crate) fn update_peak_sizes(&mut self) {
+        let stats = &mut self.report.stats;
+        stats.peak_transactions = stats.peak_transactions.max(self.transactions.len());
+        stats.peak_clients = stats.peak_clients.max(self.clients.len());
+    }
+
+    /// Sum of the amounts of the transactions under an open dispute, for each client having any.
+    #[cfg(feature = "cli")]
+    pub(crate) fn disputed_funds(&self) -> Result<HashMap<ClientId, MoneyAmount>, Error> {
+        let mut disputed_funds = HashMap::default();
+        for (_, transaction) in self.transactions.entries()? {
+            if transaction.disputed == DisputedState::Disputed {
+                add_total(
+                    disputed_funds.entry(transaction.client_id).or_default(),
+                    transaction.disputed_amount,
+                );
+            }
+        }
+        Ok(disputed_funds)
+    }
+
+    /// Credits interest on the available funds of every client, at a rate in basis points
+    /// (1/100th of a percent). Clients without positive available funds do not earn interest;
+    /// held funds do not earn interest either. Fails if the interest overflows a balance.
+    #[cfg(feature = "cli")]
+    pub(crate) fn accrue_interest(&mut self, rate_bps: u32) -> Result<(), Error> {
+        // The rate is exact as a decimal, so only the multiplication can overflow
+        let rate = Decimal::from(rate_bps) / Decimal::from(10_000);
+        for (&client_id, client) in self.clients.iter_mut() {
+            if client.available_funds.is_sign_negative() || client.available_funds.is_zero() {
+                continue;
+            }
+            let interest = client
+                .available_funds
+                .checked_mul(rate)
+                .map(MoneyAmount)
+                .ok_or(Error::BalanceOverflow(client_id))?;
+            client.available_funds = add_funds(client.available_funds, interest, client_id)?;
+            add_total(&mut self.report.accrued_interest, interest);
+        }
+        Ok(())
+    }
+
+    /// Checks that no funds have been created or destroyed, except by the processed transactions:
+    /// the clients' total funds must add up to the funds expected by the report.
+    #[cfg(feature = "cli")]
+    pub(crate) fn audit_funds(&self) -> Result<(), Error> {
+        let expected_funds = self.report.expected_funds();
+        let actual_funds = sum_total_funds(&self.clients);
+        if actual_funds != expected_funds {
+            return Err(Error::FundsAuditFailed(expected_funds, actual_funds));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the whole state as pretty JSON.
+    #[cfg(feature = "cli")]
+    pub(crate) fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(Error::JsonError)
+    }
+
+    /// Reads a state written by `to_json`, or a settled state only giving the clients and the
+    /// transactions with their kinds and amounts, for instance to replay disputes separately from
+    /// settlement.
+    #[cfg(feature = "cli")]
+    pub(crate) fn from_json(json: &str) -> Result<Self, Error> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(Error::JsonError)?;
+        let is_settled = value.get("report").is_none();
+        let mut state: Self = serde_json::from_value(value).map_err(Error::JsonError)?;
+        // The funds of a settled state are the opening funds of the run resuming it
+        if is_settled {
+            state.report.opening_funds = sum_total_funds(&state.clients);
+        }
+        // A settled state can list disputed transactions without counting them as open
+        state.open_disputes = state
+            .transactions
+            .entries()?
+            .iter()
+            .filter(|(_, transaction)| transaction.disputed == DisputedState::Disputed)
+            .count();
+        Ok(state)
+    }
+}
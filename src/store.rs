@@ -0,0 +1,255 @@
+//! Storage of the transactions that disputes can target, in memory or on disk.
+
+use crate::{error::Error, transaction::Transaction, types::TransactionId, InternalHashMap};
+#[cfg(feature = "cli")]
+use crate::{state::ProcessingState, types::ClientId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "cli")]
+use std::path::Path;
+
+/// Where the deposits and withdrawals that can be targeted by other transactions are stored.
+pub(crate) trait TransactionStore: fmt::Debug {
+    /// Returns a stored transaction.
+    fn get(&self, transaction_id: TransactionId) -> Result<Option<Transaction>, Error>;
+
+    /// Stores a transaction, replacing any transaction with the same ID.
+    fn insert(
+        &mut self,
+        transaction_id: TransactionId,
+        transaction: Transaction,
+    ) -> Result<(), Error>;
+
+    /// Removes the transactions of a client and returns them.
+    #[cfg(feature = "cli")]
+    fn remove_client(&mut self, client_id: ClientId) -> Result<Vec<Transaction>, Error>;
+
+    /// Returns every stored transaction.
+    fn entries(&self) -> Result<Vec<(TransactionId, Transaction)>, Error>;
+
+    /// Number of stored transactions.
+    fn len(&self) -> usize;
+}
+
+/// Transactions are stored in memory by default.
+impl TransactionStore for InternalHashMap<TransactionId, Transaction> {
+    fn get(&self, transaction_id: TransactionId) -> Result<Option<Transaction>, Error> {
+        Ok(InternalHashMap::get(self, &transaction_id).cloned())
+    }
+
+    fn insert(
+        &mut self,
+        transaction_id: TransactionId,
+        transaction: Transaction,
+    ) -> Result<(), Error> {
+        InternalHashMap::insert(self, transaction_id, transaction);
+        Ok(())
+    }
+
+    #[cfg(feature = "cli")]
+    fn remove_client(&mut self, client_id: ClientId) -> Result<Vec<Transaction>, Error> {
+        let transaction_ids: Vec<_> = self
+            .iter()
+            .filter(|(_, transaction)| transaction.client_id == client_id)
+            .map(|(transaction_id, _)| *transaction_id)
+            .collect();
+        Ok(transaction_ids
+            .iter()
+            .filter_map(|transaction_id| self.remove(transaction_id))
+            .collect())
+    }
+
+    fn entries(&self) -> Result<Vec<(TransactionId, Transaction)>, Error> {
+        Ok(self
+            .iter()
+            .map(|(transaction_id, transaction)| (*transaction_id, transaction.clone()))
+            .collect())
+    }
+
+    fn len(&self) -> usize {
+        InternalHashMap::len(self)
+    }
+}
+
+/// The transaction store of the processing state, whatever its backend.
+#[derive(Debug)]
+pub(crate) struct TransactionStorage(Box<dyn TransactionStore>);
+
+impl Deref for TransactionStorage {
+    type Target = dyn TransactionStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl DerefMut for TransactionStorage {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut()
+    }
+}
+
+/// Transactions are stored in memory by default.
+impl Default for TransactionStorage {
+    fn default() -> Self {
+        Self(Box::new(
+            InternalHashMap::<TransactionId, Transaction>::default(),
+        ))
+    }
+}
+
+/// Stores are equal if they hold the same transactions, whatever their backend.
+impl PartialEq for TransactionStorage {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.entries(), other.entries()) {
+            (Ok(entries), Ok(other_entries)) => {
+                InternalHashMap::from_iter(entries) == InternalHashMap::from_iter(other_entries)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Transaction stores are written as a map of the transactions, whatever their backend.
+impl Serialize for TransactionStorage {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entries = self.entries().map_err(serde::ser::Error::custom)?;
+        serializer.collect_map(entries)
+    }
+}
+
+/// Transaction stores are read back in memory.
+impl<'de> Deserialize<'de> for TransactionStorage {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let transactions: InternalHashMap<TransactionId, Transaction> =
+            Deserialize::deserialize(deserializer)?;
+        Ok(Self(Box::new(transactions)))
+    }
+}
+
+/// Transactions stored on disk with sled, for inputs whose transactions do not fit in memory.
+/// Transactions are keyed by their big-endian ID and stored as JSON.
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+struct SledTransactionStore {
+    /// The database holding the transactions.
+    db: sled::Db,
+    /// Number of stored transactions, kept here as counting them in the database is slow.
+    len: usize,
+}
+
+#[cfg(feature = "sled")]
+impl SledTransactionStore {
+    /// Opens a store in a directory, which is created if needed. Transactions already stored
+    /// there by a previous run are discarded.
+    fn open(path: &Path) -> Result<Self, Error> {
+        let db = sled::open(path).map_err(Error::TransactionStoreError)?;
+        db.clear().map_err(Error::TransactionStoreError)?;
+        Ok(Self { db, len: 0 })
+    }
+
+    /// Reads a stored transaction.
+    fn decode(value: &[u8]) -> Result<Transaction, Error> {
+        serde_json::from_slice(value).map_err(Error::JsonError)
+    }
+}
+
+#[cfg(feature = "sled")]
+impl TransactionStore for SledTransactionStore {
+    fn get(&self, transaction_id: TransactionId) -> Result<Option<Transaction>, Error> {
+        self.db
+            .get(transaction_id.0.to_be_bytes())
+            .map_err(Error::TransactionStoreError)?
+            .map(|value| Self::decode(&value))
+            .transpose()
+    }
+
+    fn insert(
+        &mut self,
+        transaction_id: TransactionId,
+        transaction: Transaction,
+    ) -> Result<(), Error> {
+        let value = serde_json::to_vec(&transaction).map_err(Error::JsonError)?;
+        let previous = self
+            .db
+            .insert(transaction_id.0.to_be_bytes(), value)
+            .map_err(Error::TransactionStoreError)?;
+        if previous.is_none() {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "cli")]
+    fn remove_client(&mut self, client_id: ClientId) -> Result<Vec<Transaction>, Error> {
+        let mut removed = Vec::new();
+        for entry in self.db.iter() {
+            let (key, value) = entry.map_err(Error::TransactionStoreError)?;
+            let transaction = Self::decode(&value)?;
+            if transaction.client_id == client_id {
+                self.db.remove(key).map_err(Error::TransactionStoreError)?;
+                self.len -= 1;
+                removed.push(transaction);
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entries(&self) -> Result<Vec<(TransactionId, Transaction)>, Error> {
+        self.db
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.map_err(Error::TransactionStoreError)?;
+                let key = <[u8; 4]>::try_from(key.as_ref())
+                    .map_err(|_| Error::CorruptedTransactionStore)?;
+                Ok((
+                    TransactionId(u32::from_be_bytes(key)),
+                    Self::decode(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Where transactions are stored.
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub(crate) enum TransactionStoreKind {
+    /// In memory.
+    #[default]
+    Memory,
+
+    /// On disk, in the directory given by `--transaction-store-path`.
+    #[cfg(feature = "sled")]
+    Sled,
+}
+
+/// Moves the transactions of a state to a store of the given kind.
+/// The path is the directory of stores on disk.
+#[cfg(feature = "cli")]
+#[cfg_attr(not(feature = "sled"), allow(unused_variables))]
+pub(crate) fn select_transaction_store(
+    state: &mut ProcessingState,
+    kind: TransactionStoreKind,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    match kind {
+        TransactionStoreKind::Memory => Ok(()),
+        #[cfg(feature = "sled")]
+        TransactionStoreKind::Sled => {
+            let path = path.ok_or(Error::MissingTransactionStorePath)?;
+            let mut store = SledTransactionStore::open(path)?;
+            for (transaction_id, transaction) in state.transactions.entries()? {
+                store.insert(transaction_id, transaction)?;
+            }
+            state.transactions = TransactionStorage(Box::new(store));
+            Ok(())
+        }
+    }
+}
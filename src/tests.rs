@@ -1,5 +1,79 @@
-use super::*;
+#![allow(clippy::unwrap_used)] // Tests are allowed to panic on unexpected values
+
+#[cfg(feature = "cli")]
+use crate::{cli::*, store::*};
+use crate::{
+    engine::*, error::*, input::*, output::*, processing::*, state::*, transaction::*, types::*,
+};
+#[cfg(feature = "cli")]
+use clap::Parser;
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use std::collections::HashMap;
+#[cfg(feature = "cli")]
+use std::fs::{self, File};
+use std::io::{self, Write};
+#[cfg(feature = "cli")]
+use std::path::PathBuf;
+
+/// Builds a list of transactions to process without having to write CSV input.
+#[derive(Default)]
+struct Scenario {
+    records: Vec<TransactionRecord>,
+}
+
+impl Scenario {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(mut self, type_string: &str, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        self.records.push(TransactionRecord {
+            type_string: type_string.to_string(),
+            client_id: ClientId(client),
+            id: TransactionId(tx),
+            amount: amount.map(MoneyAmount),
+            timestamp: None,
+        });
+        self
+    }
+
+    fn deposit(self, client: u16, tx: u32, amount: Decimal) -> Self {
+        self.record("deposit", client, tx, Some(amount))
+    }
+
+    fn withdrawal(self, client: u16, tx: u32, amount: Decimal) -> Self {
+        self.record("withdrawal", client, tx, Some(amount))
+    }
+
+    fn dispute(self, client: u16, tx: u32) -> Self {
+        self.record("dispute", client, tx, None)
+    }
+
+    fn partial_dispute(self, client: u16, tx: u32, amount: Decimal) -> Self {
+        self.record("dispute", client, tx, Some(amount))
+    }
+
+    fn resolve(self, client: u16, tx: u32) -> Self {
+        self.record("resolve", client, tx, None)
+    }
+
+    fn chargeback(self, client: u16, tx: u32) -> Self {
+        self.record("chargeback", client, tx, None)
+    }
+
+    /// Processes the transactions and returns the resulting clients.
+    fn run(self) -> Result<HashMap<ClientId, Client>, Error> {
+        let mut state = ProcessingState::default();
+        process_records(
+            self.records,
+            &mut state,
+            &mut ProcessingOptions::default(),
+            io::sink(),
+        )?;
+        Ok(state.clients)
+    }
+}
 
 // Tests that invalid input returns an error
 #[test]
@@ -17,7 +91,7 @@ fn test_deposits() -> Result<(), Error> {
 	deposit, 1, 1, 1.0
 	deposit, 2, 2, 2.0
 	deposit, 1, 3, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let (result, _) = process_transactions(input.as_bytes())?;
     assert_eq!(result.len(), 2);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -46,7 +120,7 @@ fn test_invalid_deposits() -> Result<(), Error> {
 	deposit, 1, 1, -1.0
 	deposit, 2, 2, 2.0
 	deposit, 1, 3, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let (result, _) = process_transactions(input.as_bytes())?;
     assert_eq!(result.len(), 2);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -69,7 +143,7 @@ fn test_invalid_deposits() -> Result<(), Error> {
 	deposit, 1, 1, 0.0
 	deposit, 2, 2, 2.0
 	deposit, 1, 3, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let (result, _) = process_transactions(input.as_bytes())?;
     assert_eq!(result.len(), 2);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -92,7 +166,7 @@ fn test_invalid_deposits() -> Result<(), Error> {
 	deposit, 1, 1
 	deposit, 2, 2, 2.0
 	deposit, 1, 3, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let (result, _) = process_transactions(input.as_bytes())?;
     assert_eq!(result.len(), 2);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -117,13 +191,13 @@ fn test_invalid_deposits() -> Result<(), Error> {
 // Tests that a deposits and withdrawals return the expected result
 #[test]
 fn test_withdrawals() -> Result<(), Error> {
-    let input = r#"type, client, tx, amount
-	deposit, 1, 1, 1.0
-	deposit, 2, 2, 2.0
-	deposit, 1, 3, 2.0
-	withdrawal, 1, 4, 1.5
-	withdrawal, 2, 5, 3.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(1.0))
+        .deposit(2, 2, dec!(2.0))
+        .deposit(1, 3, dec!(2.0))
+        .withdrawal(1, 4, dec!(1.5))
+        .withdrawal(2, 5, dec!(3.0))
+        .run()?;
     assert_eq!(result.len(), 2);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -149,32 +223,33 @@ fn test_withdrawals() -> Result<(), Error> {
 // that they are ignored
 #[test]
 fn test_dispute_and_resolve() -> Result<(), Error> {
-    let input = r#"type, client, tx, amount
-    deposit,    1, 1,  2.0
-    resolve,    1, 1
-    withdrawal, 1, 2,  1.5
-    dispute,    1, 2
-    resolve,    1, 2
-    dispute,    1, 2
-    deposit,    1, 10, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(2.0))
+        .resolve(1, 1)
+        .withdrawal(1, 2, dec!(1.5))
+        .dispute(1, 2)
+        .resolve(1, 2)
+        .dispute(1, 2)
+        .deposit(1, 10, dec!(2.0))
+        .run()?;
     assert_eq!(result.len(), 1);
+    // The resolved withdrawal has been credited back
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
         &Client {
-            available_funds: dec!(2.5).into(),
+            available_funds: dec!(4.0).into(),
             held_funds: dec!(0).into(),
             is_locked: false,
         }
     );
 
-    let input = r#"type, client, tx, amount
-	deposit,    1, 1,  2.0
-	dispute,    1, 1
-	resolve,    1, 1
-	dispute,    1, 2
-	deposit,    1, 10, 2.0"#;
-    let result = process_transactions(input.as_bytes())?;
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(2.0))
+        .dispute(1, 1)
+        .resolve(1, 1)
+        .dispute(1, 2)
+        .deposit(1, 10, dec!(2.0))
+        .run()?;
     assert_eq!(result.len(), 1);
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
@@ -191,22 +266,2376 @@ fn test_dispute_and_resolve() -> Result<(), Error> {
 // Tests a dispute and a chargeback
 #[test]
 fn test_dispute_and_chargeback() -> Result<(), Error> {
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(2.0))
+        .withdrawal(1, 2, dec!(1.5))
+        .dispute(1, 2)
+        .chargeback(1, 2)
+        .deposit(1, 10, dec!(2.0)) // This won't be allowed since the account has been frozen
+        .run()?;
+    assert_eq!(result.len(), 1);
+    // The charged back withdrawal has been credited back
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(2.0).into(),
+            held_funds: dec!(0).into(),
+            is_locked: true,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that echoed records show the values extracted from a spaced input
+#[test]
+fn test_echo_input() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	  deposit ,  1 ,  1 ,  1.5
+	dispute,1,1"#;
+    let mut options = ProcessingOptions {
+        echo_input: true,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Parsed record: TransactionRecord { type_string: \"deposit\", client_id: ClientId(1), \
+         id: TransactionId(1), amount: Some(MoneyAmount(1.5)), timestamp: None }\n\
+         Parsed record: TransactionRecord { type_string: \"dispute\", client_id: ClientId(1), \
+         id: TransactionId(1), amount: None, timestamp: None }\n"
+    );
+
+    Ok(())
+}
+
+// Tests that a duplicate chargeback leaves held funds and the lock state unchanged
+#[test]
+fn test_duplicate_chargeback() -> Result<(), Error> {
     let input = r#"type, client, tx, amount
-	deposit,    1, 1,  2.0
-	withdrawal, 1, 2,  1.5
+	deposit,    1, 1,  5.0
+	deposit,    1, 2,  3.0
 	dispute,    1, 2
 	chargeback, 1, 2
-	deposit,    1, 10, 2.0"#; // This won't be allowed since the account has been frozen
-    let result = process_transactions(input.as_bytes())?;
-    assert_eq!(result.len(), 1);
+	chargeback, 1, 2"#;
+    let (result, _) = process_transactions(input.as_bytes())?;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(5).into(),
+            held_funds: dec!(0).into(),
+            is_locked: true,
+        }
+    );
+
+    // The transaction state alone must reject the duplicate, even if the account lock was bypassed
+    let mut client = Client {
+        available_funds: dec!(5).into(),
+        held_funds: dec!(0).into(),
+        is_locked: false,
+    };
+    let mut transactions = HashMap::from_iter([(
+        TransactionId(2),
+        Transaction {
+            client_id: ClientId(1),
+            kind: TransactionKind::Deposit,
+            amount: dec!(3).into(),
+            disputed: DisputedState::ChargedBack,
+            disputed_amount: dec!(3).into(),
+            settlement: SettlementState::Settled,
+        },
+    )]);
+    let result = process_chargeback(
+        &mut client,
+        ClientId(1),
+        TransactionId(2),
+        &mut transactions,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::TransactionNotUnderDispute(TransactionId(2)))
+    ));
+    assert_eq!(client.held_funds, dec!(0).into());
+    assert!(!client.is_locked);
+
+    Ok(())
+}
+
+// Tests that a custom validator can reject records
+#[test]
+fn test_custom_validator() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 1.25
+	deposit,    1, 2, 1.005
+	withdrawal, 1, 3, 0.001
+	withdrawal, 1, 4, 0.25"#;
+    // Only allow amounts that are a multiple of 0.01
+    let mut options = ProcessingOptions {
+        validator: Some(Box::new(|record| match record.amount {
+            Some(amount) if !(*amount * dec!(100)).fract().is_zero() => {
+                Err(Error::InvalidAmount(amount))
+            }
+            _ => Ok(()),
+        })),
+        ..Default::default()
+    };
+    let result =
+        process_transactions_with_options(input.as_bytes(), &mut options, io::sink())?.clients;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(1).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that the schema describes the record fields with their types
+#[cfg(feature = "cli")]
+#[test]
+fn test_schema() -> Result<(), Error> {
+    let mut output = Vec::new();
+    write_schema(&mut output)?;
+    let schema: serde_json::Value = serde_json::from_slice(&output).map_err(Error::JsonError)?;
+    let properties = schema["properties"].as_object().unwrap();
+    assert_eq!(properties.len(), 5);
+    assert_eq!(properties["type"]["type"], "string");
+    assert_eq!(properties["client"]["type"], "integer");
+    assert_eq!(properties["client"]["format"], "uint16");
+    assert_eq!(properties["tx"]["type"], "integer");
+    assert_eq!(properties["tx"]["format"], "uint32");
+    assert_eq!(
+        properties["amount"]["type"],
+        serde_json::json!(["string", "number", "null"])
+    );
+    assert_eq!(
+        properties["timestamp"]["type"],
+        serde_json::json!(["integer", "null"])
+    );
+    assert_eq!(
+        schema["required"],
+        serde_json::json!(["type", "client", "tx"])
+    );
+
+    Ok(())
+}
+
+// Tests merging two results with an overlapping client
+#[cfg(feature = "cli")]
+#[test]
+fn test_merge_results() -> Result<(), Error> {
+    let first = r#"client,available,held,total,locked
+1,1.5,0.5,2.0,false
+2,3.0,0,3.0,false"#;
+    let second = r#"client,available,held,total,locked
+3,4.0,0,4.0,0
+1,2.0,1.0,3.0,1"#;
+    let mut clients = HashMap::default();
+    merge_result(first.as_bytes(), &mut clients)?;
+    merge_result(second.as_bytes(), &mut clients)?;
+    assert_eq!(clients.len(), 3);
+    assert_eq!(
+        clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(3.5).into(),
+            held_funds: dec!(1.5).into(),
+            is_locked: true,
+        }
+    );
+    assert_eq!(
+        clients.get(&ClientId(2)).unwrap(),
+        &Client {
+            available_funds: dec!(3).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        clients.get(&ClientId(3)).unwrap(),
+        &Client {
+            available_funds: dec!(4).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    // Merging balances that overflow is an error
+    clients.get_mut(&ClientId(3)).unwrap().available_funds = MoneyAmount(Decimal::MAX);
+    assert!(matches!(
+        merge_result(second.as_bytes(), &mut clients),
+        Err(Error::BalanceOverflow(ClientId(3)))
+    ));
+
+    Ok(())
+}
+
+// Tests that the most recent deposits cannot be withdrawn until they mature
+#[test]
+fn test_hold_new_deposits() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 10.0
+	withdrawal, 1, 2, 5.0
+	deposit,    1, 3, 5.0
+	withdrawal, 1, 4, 12.0
+	withdrawal, 1, 5, 10.0"#;
+    let mut options = ProcessingOptions {
+        hold_new_deposits: 1,
+        ..Default::default()
+    };
+    let result =
+        process_transactions_with_options(input.as_bytes(), &mut options, io::sink())?.clients;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(5).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that immature deposits whose sum overflows leave nothing to withdraw instead of panicking
+#[test]
+fn test_hold_new_deposits_overflow() -> Result<(), Error> {
+    // Opening balances can be negative, so the immature deposits can exceed the balance
+    let mut state = ProcessingState::default();
+    state.clients.insert(
+        ClientId(1),
+        Client {
+            available_funds: dec!(-30000000000000000000000000000).into(),
+            ..Default::default()
+        },
+    );
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(70000000000000000000000000000))
+        .deposit(1, 2, dec!(35000000000000000000000000000))
+        .withdrawal(1, 3, dec!(1))
+        .records;
+    let mut options = ProcessingOptions {
+        hold_new_deposits: 2,
+        ..Default::default()
+    };
+    process_records(records, &mut state, &mut options, io::sink())?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(75000000000000000000000000000).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        state.report.stats.rejections.get("NotEnoughAvailableFunds"),
+        Some(&1)
+    );
+
+    Ok(())
+}
+
+// Tests that the result header matches the fields of a result entry, so that they cannot drift
+#[test]
+fn test_result_header_matches_record() -> Result<(), Error> {
+    let record = AccountRecord::new(ClientId(1), &Client::default());
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .serialize(record)
+        .map_err(Error::SerializationError)?;
+    let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    let mut lines = output.lines();
+    let header = lines.next().unwrap();
+    let row = lines.next().unwrap();
+    assert_eq!(header, RESULT_HEADER.join(","));
+    assert_eq!(row.split(',').count(), RESULT_HEADER.len());
+
+    Ok(())
+}
+
+// Tests following a file that is being appended to, including a partially written line
+#[cfg(feature = "cli")]
+#[test]
+fn test_follow() -> Result<(), Error> {
+    let mut file = tempfile::NamedTempFile::new().map_err(Error::ReadError)?;
+    let mut follower = Follower::new(
+        file.reopen().map_err(Error::ReadError)?,
+        ProcessingState::default(),
+    );
+    let mut options = ProcessingOptions::default();
+
+    write!(file, "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndepos").unwrap();
+    follower.poll(&mut options, io::sink())?;
+    assert_eq!(
+        follower.state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(2).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    // The partial line must not be processed until its end has been written
+    write!(file, "it, 1, 2, 3.0").unwrap();
+    follower.poll(&mut options, io::sink())?;
+    assert_eq!(
+        follower
+            .state
+            .clients
+            .get(&ClientId(1))
+            .unwrap()
+            .available_funds,
+        dec!(2).into()
+    );
+
+    write!(file, "\nwithdrawal, 1, 3, 1.0\n").unwrap();
+    follower.poll(&mut options, io::sink())?;
+    assert_eq!(
+        follower.state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(4).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that invalid UTF-8 in a followed file is reported with its position in the file
+#[cfg(feature = "cli")]
+#[test]
+fn test_follow_invalid_utf8() -> Result<(), Error> {
+    let mut file = tempfile::NamedTempFile::new().map_err(Error::ReadError)?;
+    let mut follower = Follower::new(
+        file.reopen().map_err(Error::ReadError)?,
+        ProcessingState::default(),
+    );
+    let mut options = ProcessingOptions::default();
+
+    write!(file, "type,client,tx,amount\ndeposit,1,1,2.0\n").unwrap();
+    follower.poll(&mut options, io::sink())?;
+    file.write_all(b"deposit,1,2,3.0\ndeposit,1,3,\xff\n")
+        .unwrap();
+    assert!(matches!(
+        follower.poll(&mut options, io::sink()),
+        Err(Error::InvalidUtf8(54))
+    ));
+
+    Ok(())
+}
+
+// Tests that disputes are rejected and transactions are not stored when disputes are disabled,
+// though their IDs still cannot be reused
+#[test]
+fn test_no_disputes() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 2.0
+	withdrawal, 1, 2, 0.5
+	dispute,    1, 1
+	chargeback, 1, 1
+	deposit,    1, 2, 3.0"#;
+    let mut options = ProcessingOptions {
+        no_disputes: true,
+        ..Default::default()
+    };
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_transactions_into(input.as_bytes(), &mut state, &mut options, &mut log)?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(1.5).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(state.transactions.len(), 0);
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 1: disputes are disabled\n\
+         Error processing transaction: transaction 1: disputes are disabled\n\
+         Error processing transaction: transaction ID 2 is already used\n"
+    );
+
+    Ok(())
+}
+
+// Tests that invalid UTF-8 input is reported with the position of the faulty record
+#[test]
+fn test_invalid_utf8() {
+    let input = b"type, client, tx, amount\ndeposit, 1, 1, 1.0\nd\xffposit, 1, 2, 1.0\n";
+    let result = process_transactions(input.as_slice());
+    assert!(matches!(result, Err(Error::InvalidUtf8(44))));
+}
+
+// Tests that the daily withdrawal limit applies per client and resets on the next UTC day
+#[test]
+fn test_daily_withdrawal_limit() -> Result<(), Error> {
+    // 1704067200 is 2024-01-01T00:00:00Z
+    let input = r#"type, client, tx, amount, timestamp
+	deposit,    1, 1, 100.0, 1704067200
+	deposit,    2, 2, 100.0, 1704067200
+	withdrawal, 1, 3, 6.0,   1704067300
+	withdrawal, 1, 4, 4.0,   1704153599
+	withdrawal, 1, 5, 0.01,  1704153599
+	withdrawal, 2, 6, 10.0,  1704153599
+	withdrawal, 1, 7, 10.0,  1704153600
+	withdrawal, 1, 8, 1.0"#;
+    let mut options = ProcessingOptions {
+        daily_withdrawal_limit: Some(dec!(10).into()),
+        ..Default::default()
+    };
+    let result =
+        process_transactions_with_options(input.as_bytes(), &mut options, io::sink())?.clients;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(80).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        result.get(&ClientId(2)).unwrap(),
+        &Client {
+            available_funds: dec!(90).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that a daily withdrawal total overflowing is rejected instead of panicking
+#[test]
+fn test_daily_withdrawal_limit_overflow() -> Result<(), Error> {
+    let mut records = Scenario::new()
+        .deposit(1, 1, dec!(70000000000000000000000000000))
+        .withdrawal(1, 2, dec!(40000000000000000000000000000))
+        .withdrawal(1, 3, dec!(40000000000000000000000000000))
+        .records;
+    for record in &mut records {
+        record.timestamp = Some(Timestamp(1704067200));
+    }
+    let mut state = ProcessingState::default();
+    let mut options = ProcessingOptions {
+        daily_withdrawal_limit: Some(dec!(50000000000000000000000000000).into()),
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    process_records(records, &mut state, &mut options, &mut log)?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: client 1: balance overflow\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(30000000000000000000000000000).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that the disputed column sums the open disputes of each client and matches the held funds
+#[cfg(feature = "cli")]
+#[test]
+fn test_disputed_funds_column() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 2.0
+	deposit, 1, 2, 3.5
+	deposit, 1, 3, 1.0
+	deposit, 2, 4, 4.0
+	dispute, 1, 1
+	dispute, 1, 2
+	dispute, 1, 3
+	resolve, 1, 3"#;
+    let state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    let disputed_funds = state.disputed_funds()?;
+    assert_eq!(disputed_funds.len(), 1);
+    assert_eq!(
+        disputed_funds.get(&ClientId(1)).copied(),
+        Some(state.clients.get(&ClientId(1)).unwrap().held_funds)
+    );
+    assert_eq!(
+        disputed_funds.get(&ClientId(1)).copied(),
+        Some(dec!(5.5).into())
+    );
+
+    let mut output = Vec::new();
+    write_result(
+        &state.clients,
+        Some(&disputed_funds),
+        &ResultOptions::default(),
+        &mut output,
+    )?;
+    let output = String::from_utf8(output).unwrap();
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "1,1,5.5,6.5,false,5.5",
+            "2,4,0,4,false,0",
+            "client,available,held,total,locked,disputed",
+        ]
+    );
+
+    Ok(())
+}
+
+// Tests a resolve after a chargeback, rejected by default and unlocking the account when allowed
+#[test]
+fn test_resolve_after_chargeback() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 10.0
+	deposit,    1, 2, 5.0
+	dispute,    1, 2
+	chargeback, 1, 2
+	resolve,    1, 2
+	deposit,    1, 3, 1.0"#;
+    let mut log = Vec::new();
+    let result = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?
+    .clients;
     assert_eq!(
         result.get(&ClientId(1)).unwrap(),
         &Client {
-            available_funds: dec!(-1).into(),
+            available_funds: dec!(10).into(),
             held_funds: dec!(0).into(),
             is_locked: true,
         }
     );
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 2 has been charged back and cannot be resolved\n\
+         Error processing transaction: client account 1 is locked\n"
+    );
+
+    let mut options = ProcessingOptions {
+        allow_resolve_after_chargeback: true,
+        ..Default::default()
+    };
+    let result = process_transactions_with_options(input.as_bytes(), &mut options, io::sink())?;
+    // The account is unlocked, but the charged back funds are not restored
+    assert_eq!(
+        result.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(11).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        result.transactions.get(TransactionId(2))?.unwrap().disputed,
+        DisputedState::Resolved
+    );
+
+    Ok(())
+}
+
+// Tests that records without every column are rejected in strict mode, even if the missing
+// column would be empty
+#[test]
+fn test_strict_columns() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 2.0
+	dispute, 1, 1,
+	resolve, 1, 1
+	deposit, 1, 2"#;
+    let mut options = ProcessingOptions {
+        strict_columns: true,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    let result = process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?;
+    assert_eq!(
+        result.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(0).into(),
+            held_funds: dec!(2).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 1: expected 4 columns, found 3\n\
+         Error processing transaction: transaction 2: expected 4 columns, found 3\n"
+    );
+
+    Ok(())
+}
+
+// Tests the peak sizes of the stored transactions and clients
+#[test]
+fn test_peak_sizes() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 2.0
+	deposit,    2, 2, 2.0
+	withdrawal, 1, 3, 1.0
+	withdrawal, 2, 4, 5.0
+	dispute,    1, 1
+	deposit,    3, 5, 1.0"#;
+    let result = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    assert_eq!(
+        result.report,
+        ProcessingReport {
+            stats: ProcessingStats {
+                records: 6,
+                deposits: 3,
+                withdrawals: 1,
+                disputes: 1,
+                rejections: HashMap::from_iter([("NotEnoughAvailableFunds".to_string(), 1)]),
+                sample_transaction_ids: HashMap::from_iter([(
+                    "NotEnoughAvailableFunds".to_string(),
+                    TransactionId(4),
+                )]),
+                peak_transactions: 4,
+                peak_clients: 3,
+                ..Default::default()
+            },
+            deposited_funds: MoneyAmount(dec!(5.0)),
+            withdrawn_funds: MoneyAmount(dec!(1.0)),
+            ..Default::default()
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that generated transactions are reproducible and valid
+#[cfg(feature = "cli")]
+#[test]
+fn test_generate_transactions() -> Result<(), Error> {
+    let mut first = Vec::new();
+    generate_transactions(10, 1000, 42, &mut first)?;
+    let mut second = Vec::new();
+    generate_transactions(10, 1000, 42, &mut second)?;
+    assert_eq!(first, second);
+    assert_eq!(first.iter().filter(|&&byte| byte == b'\n').count(), 1001);
+
+    let mut other = Vec::new();
+    generate_transactions(10, 1000, 43, &mut other)?;
+    assert_ne!(first, other);
+
+    // No transaction should be rejected
+    let mut log = Vec::new();
+    process_transactions_with_options(
+        first.as_slice(),
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(String::from_utf8(log).unwrap(), "");
+
+    Ok(())
+}
+
+// Tests rendering the locked flag as a number
+#[test]
+fn test_bool_as_int() -> Result<(), Error> {
+    let clients = HashMap::from_iter([(
+        ClientId(1),
+        Client {
+            available_funds: dec!(1).into(),
+            held_funds: dec!(0).into(),
+            is_locked: true,
+        },
+    )]);
+    let options = ResultOptions {
+        bool_as_int: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(&clients, None, &options, &mut output)?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1,0,1,1\n"
+    );
+
+    Ok(())
+}
+
+// Tests that giving the same file twice is detected, even through different paths
+#[cfg(feature = "cli")]
+#[test]
+fn test_duplicate_input_file() -> Result<(), Error> {
+    let directory = tempfile::tempdir().map_err(Error::ReadError)?;
+    let first = directory.path().join("first.csv");
+    let second = directory.path().join("second.csv");
+    File::create(&first).map_err(Error::ReadError)?;
+    File::create(&second).map_err(Error::ReadError)?;
+    check_duplicate_files(&[first.clone(), second.clone()])?;
+
+    let result = check_duplicate_files(&[first.clone(), second.clone(), first.clone()]);
+    assert!(matches!(result, Err(Error::DuplicateInputFile(path)) if path == first));
+
+    let other_path = directory.path().join(".").join("second.csv");
+    let result = check_duplicate_files(&[second, other_path.clone()]);
+    assert!(matches!(result, Err(Error::DuplicateInputFile(path)) if path == other_path));
+
+    Ok(())
+}
+
+// Tests the net changes produced by a run against seeded opening balances
+#[cfg(feature = "cli")]
+#[test]
+fn test_deltas() -> Result<(), Error> {
+    let opening_balances = r#"client,available,held,total,locked
+1,10.0,0,10.0,false
+2,5.0,0,5.0,false
+3,7.0,0,7.0,false"#;
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 2.5
+	withdrawal, 2, 2, 1.0
+	deposit,    2, 3, 3.0
+	dispute,    2, 3
+	chargeback, 2, 3
+	deposit,    4, 4, 1.0"#;
+    let mut state = ProcessingState::default();
+    merge_result(opening_balances.as_bytes(), &mut state.clients)?;
+    let opening_clients = state.clients.clone();
+    process_transactions_into(
+        input.as_bytes(),
+        &mut state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+
+    let mut output = Vec::new();
+    write_deltas(&opening_clients, &state.clients, &mut output)?;
+    let output = String::from_utf8(output).unwrap();
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "1,2.5,0,",
+            "2,-1,0,true",
+            "4,1,0,",
+            "client,available,held,locked",
+        ]
+    );
+
+    Ok(())
+}
+
+// Tests that a net change overflowing an amount is an error instead of a panic
+#[cfg(feature = "cli")]
+#[test]
+fn test_deltas_overflow() -> Result<(), Error> {
+    let mut state = ProcessingState::default();
+    state.clients.insert(
+        ClientId(1),
+        Client {
+            available_funds: dec!(-30000000000000000000000000000).into(),
+            ..Default::default()
+        },
+    );
+    let opening_clients = state.clients.clone();
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(70000000000000000000000000000))
+        .deposit(1, 2, dec!(30000000000000000000000000000))
+        .records;
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+
+    let result = write_deltas(&opening_clients, &state.clients, io::sink());
+    assert!(matches!(result, Err(Error::BalanceOverflow(ClientId(1)))));
+
+    Ok(())
+}
+
+// Tests that a chargeback of a partially disputed deposit only removes the disputed part
+#[test]
+fn test_partial_dispute_and_chargeback() -> Result<(), Error> {
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(100.0))
+        .partial_dispute(1, 1, dec!(40.0))
+        .chargeback(1, 1)
+        .run()?;
+    // The undisputed remainder stays available
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(60.0)),
+            held_funds: MoneyAmount(dec!(0.0)),
+            is_locked: true,
+        }
+    );
+
+    // The undisputed remainder cannot be disputed afterwards
+    let mut state = ProcessingState::default();
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(100.0))
+        .partial_dispute(1, 1, dec!(40.0))
+        .resolve(1, 1)
+        .partial_dispute(1, 1, dec!(60.0))
+        .records;
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 1 already under dispute\n"
+    );
+
+    // A dispute cannot exceed the amount of its target transaction
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(100.0))
+        .partial_dispute(1, 1, dec!(100.5))
+        .run()?;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(100.0))
+    );
+
+    Ok(())
+}
+
+// Tests that transactions of a locked client are tallied instead of logged
+#[test]
+fn test_tally_locked_rejections() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(10.0))
+        .deposit(1, 2, dec!(5.0))
+        .dispute(1, 1)
+        .chargeback(1, 1)
+        .deposit(1, 3, dec!(1.0))
+        .withdrawal(1, 4, dec!(1.0))
+        .dispute(1, 2)
+        .deposit(2, 5, dec!(1.0))
+        .records;
+    let mut state = ProcessingState::default();
+    let mut options = ProcessingOptions {
+        tally_locked_rejections: true,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    process_records(records, &mut state, &mut options, &mut log)?;
+    assert!(log.is_empty());
+    assert_eq!(
+        state.report.locked_rejections,
+        HashMap::from_iter([(ClientId(1), 3)])
+    );
+    // Tallied rejections are still rejections in the stats
+    assert_eq!(state.report.stats.rejected(), 3);
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(5.0))
+    );
+
+    Ok(())
+}
+
+// Tests that transactions grouped by client are written as each client is finalized
+#[cfg(feature = "cli")]
+#[test]
+fn test_assume_sorted_by_client() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 10.0
+	withdrawal, 1, 2, 4.0
+	deposit,    2, 3, 5.0
+	dispute,    2, 3
+	deposit,    3, 4, 1.0"#;
+    let mut state = ProcessingState::default();
+    let mut output = Vec::new();
+    process_sorted_transactions_into(
+        input.as_bytes(),
+        &mut state,
+        &mut ProcessingOptions::default(),
+        false,
+        &ResultOptions::default(),
+        &mut output,
+        io::sink(),
+    )?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,6,0,6,false\n\
+         2,0,5,5,false\n\
+         3,1,0,1,false\n"
+    );
+    assert!(state.clients.is_empty());
+    assert_eq!(state.transactions.len(), 0);
+    assert_eq!(state.report.stats.peak_clients, 1);
+
+    Ok(())
+}
+
+// Tests that a client appearing again after its transactions ended is an error
+#[cfg(feature = "cli")]
+#[test]
+fn test_assume_sorted_by_client_reappearance() {
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 10.0
+	deposit, 2, 2, 5.0
+	deposit, 1, 3, 1.0"#;
+    let result = process_sorted_transactions_into(
+        input.as_bytes(),
+        &mut ProcessingState::default(),
+        &mut ProcessingOptions::default(),
+        false,
+        &ResultOptions::default(),
+        io::sink(),
+        io::sink(),
+    );
+    assert!(matches!(result, Err(Error::ClientNotGrouped(ClientId(1)))));
+}
+
+// Tests that the processing state survives a JSON round trip
+#[cfg(feature = "cli")]
+#[test]
+fn test_state_json_round_trip() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(10.0))
+        .deposit(1, 2, dec!(5.0))
+        .partial_dispute(1, 1, dec!(4.0))
+        .deposit(2, 3, dec!(3.0))
+        .dispute(2, 3)
+        .chargeback(2, 3)
+        .withdrawal(1, 4, dec!(1.5))
+        .records;
+    let mut state = ProcessingState::default();
+    let mut options = ProcessingOptions {
+        hold_new_deposits: 1,
+        ..Default::default()
+    };
+    process_records(records, &mut state, &mut options, io::sink())?;
+
+    let json = state.to_json()?;
+    let loaded_state = ProcessingState::from_json(&json)?;
+    assert_eq!(loaded_state, state);
+    assert_eq!(
+        loaded_state.transactions.get(TransactionId(1))?.unwrap(),
+        Transaction {
+            client_id: ClientId(1),
+            kind: TransactionKind::Deposit,
+            amount: MoneyAmount(dec!(10.0)),
+            disputed: DisputedState::Disputed,
+            disputed_amount: MoneyAmount(dec!(4.0)),
+            settlement: SettlementState::Settled,
+        }
+    );
+    assert_eq!(
+        loaded_state.clients.get(&ClientId(1)).unwrap().held_funds,
+        MoneyAmount(dec!(4.0))
+    );
+
+    Ok(())
+}
+
+// Tests the full lifecycle of a disputed withdrawal: the chargeback reverses the withdrawal
+#[test]
+fn test_withdrawal_dispute_and_chargeback() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(100.0))
+        .withdrawal(1, 2, dec!(30.0))
+        .dispute(1, 2)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut options = ProcessingOptions::default();
+    process_records(records, &mut state, &mut options, io::sink())?;
+    // The disputed withdrawal is held as a pending credit
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(70.0)),
+            held_funds: MoneyAmount(dec!(30.0)),
+            is_locked: false,
+        }
+    );
+
+    process_records(
+        Scenario::new().chargeback(1, 2).records,
+        &mut state,
+        &mut options,
+        io::sink(),
+    )?;
+    // The withdrawn funds are credited back and the account is locked
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(100.0)),
+            held_funds: MoneyAmount(dec!(0.0)),
+            is_locked: true,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that quiet errors are only counted and verbose errors include the record
+#[cfg(feature = "cli")]
+#[test]
+fn test_error_verbosity() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 1.0
+	withdrawal, 1, 2, 5.0
+	dispute,    1, 3"#;
+    let mut options = ProcessingOptions {
+        error_verbosity: ErrorVerbosity::Quiet,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    let state = process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?;
+    assert!(log.is_empty());
+    assert_eq!(state.report.stats.rejected(), 2);
+
+    let input = r#"type, client, tx, amount
+	dispute, 1, 3"#;
+    let mut options = ProcessingOptions {
+        error_verbosity: ErrorVerbosity::Verbose,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: unknown transaction ID: 3 (record: TransactionRecord { \
+         type_string: \"dispute\", client_id: ClientId(1), id: TransactionId(3), amount: None, \
+         timestamp: None })\n"
+    );
+
+    Ok(())
+}
+
+// Tests that clients with the same ID in different files are isolated, unlike transaction IDs
+#[cfg(feature = "cli")]
+#[test]
+fn test_namespace_by_file() -> Result<(), Error> {
+    let first_input = r#"type, client, tx, amount
+	deposit, 1, 1, 10.0"#;
+    let second_input = r#"type, client, tx, amount
+	deposit, 1, 2, 3.0
+	dispute, 1, 1
+	deposit, 2, 1, 5.0"#;
+    let mut log = Vec::new();
+    let states = process_namespaced_transactions(
+        vec![first_input.as_bytes(), second_input.as_bytes()],
+        TransactionStoreKind::Memory,
+        None,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    // The dispute cannot target a transaction of the first file, but its ID cannot be reused
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: unknown transaction ID: 1\n\
+         Error processing transaction: transaction ID 1 is already used\n"
+    );
+
+    let mut output = Vec::new();
+    write_namespaced_result(
+        &states,
+        false,
+        ResultFormat::Csv,
+        &ResultOptions::default(),
+        &mut output,
+    )?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1:1,10,0,10,false\n\
+         2:1,3,0,3,false\n"
+    );
+
+    let mut output = Vec::new();
+    write_namespaced_result(
+        &states,
+        false,
+        ResultFormat::Json,
+        &ResultOptions::default(),
+        &mut output,
+    )?;
+    let records: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(records[1]["client"], "2:1");
+    assert_eq!(records[1]["total"], "3");
+
+    let mut output = Vec::new();
+    write_namespaced_deltas(&states, &mut output)?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,locked\n\
+         1:1,10,0,\n\
+         2:1,3,0,\n"
+    );
+
+    // The summary covers every tenant
+    let mut stats = ProcessingStats::default();
+    for state in &states {
+        stats.merge(&state.report.stats);
+    }
+    assert_eq!(
+        stats.to_string(),
+        "processed 4 records: 2 rejected (1 DuplicateTransactionId, 1 UnknownTransactionId)"
+    );
+
+    Ok(())
+}
+
+// Tests that the funds audit passes after regular processing and detects a broken state
+#[cfg(feature = "cli")]
+#[test]
+fn test_audit_funds() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(10.0))
+        .deposit(1, 2, dec!(5.0))
+        .withdrawal(1, 3, dec!(3.0))
+        .dispute(1, 3)
+        .dispute(1, 1)
+        .chargeback(1, 1)
+        .deposit(2, 4, dec!(2.0))
+        .partial_dispute(2, 4, dec!(0.5))
+        .records;
+    let mut state = ProcessingState::default();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    state.audit_funds()?;
+
+    // Funds appearing out of nowhere are detected
+    *state.clients.get_mut(&ClientId(2)).unwrap().available_funds += dec!(1.0);
+    assert!(matches!(
+        state.audit_funds(),
+        Err(Error::FundsAuditFailed(expected, actual))
+            if expected == MoneyAmount(dec!(7.0)) && actual == MoneyAmount(dec!(8.0))
+    ));
+
+    Ok(())
+}
+
+// Tests that balances whose sum overflows fail the funds audit instead of panicking
+#[cfg(feature = "cli")]
+#[test]
+fn test_audit_funds_overflow() {
+    let mut state = ProcessingState::default();
+    for client_id in 1..=2 {
+        state.clients.insert(
+            ClientId(client_id),
+            Client {
+                available_funds: MoneyAmount(Decimal::MAX),
+                ..Default::default()
+            },
+        );
+    }
+    assert!(matches!(
+        state.audit_funds(),
+        Err(Error::FundsAuditFailed(expected, actual))
+            if expected.is_zero() && actual == MoneyAmount(Decimal::MAX)
+    ));
+}
+
+// Tests that rejected rows are written with the input columns and can be submitted again
+#[cfg(feature = "cli")]
+#[test]
+fn test_dump_errors_csv() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 1.0
+	withdrawal, 1, 2, 5.0
+	dispute,    1, 3"#;
+    let rejected = tempfile::NamedTempFile::new().map_err(Error::ReadError)?;
+    let mut options = ProcessingOptions {
+        rejected_records: Some(RejectedRecordWriter::new(Box::new(
+            rejected.reopen().map_err(Error::ReadError)?,
+        ))),
+        ..Default::default()
+    };
+    let mut state = ProcessingState::default();
+    process_transactions_into(input.as_bytes(), &mut state, &mut options, io::sink())?;
+    options.flush_rejected_records()?;
+    let rejected_input = fs::read_to_string(rejected.path()).map_err(Error::ReadError)?;
+    assert_eq!(
+        rejected_input,
+        "type,client,tx,amount\nwithdrawal,1,2,5.0\ndispute,1,3\n"
+    );
+
+    // Once the client has enough funds, the rejected withdrawal can be submitted again
+    let fix = r#"type, client, tx, amount
+	deposit, 1, 4, 5.0"#;
+    let mut options = ProcessingOptions::default();
+    process_transactions_into(fix.as_bytes(), &mut state, &mut options, io::sink())?;
+    process_transactions_into(
+        rejected_input.as_bytes(),
+        &mut state,
+        &mut options,
+        io::sink(),
+    )?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(1.0))
+    );
+
+    Ok(())
+}
+
+// Tests that rejected rows from inputs with another column order are written under the first header
+#[cfg(feature = "cli")]
+#[test]
+fn test_dump_errors_csv_column_order() -> Result<(), Error> {
+    let first = "type,client,tx,amount\nwithdrawal,1,1,5.0\n";
+    let second = "client,tx,type,amount\n2,2,withdrawal,3.0\n2,3,dispute\n";
+    let third = "type,client,tx,amount,timestamp\nwithdrawal,3,4,1.0,100\n";
+    let rejected = tempfile::NamedTempFile::new().map_err(Error::ReadError)?;
+    let mut options = ProcessingOptions {
+        rejected_records: Some(RejectedRecordWriter::new(Box::new(
+            rejected.reopen().map_err(Error::ReadError)?,
+        ))),
+        ..Default::default()
+    };
+    let mut state = ProcessingState::default();
+    process_transactions_into(first.as_bytes(), &mut state, &mut options, io::sink())?;
+    process_transactions_into(second.as_bytes(), &mut state, &mut options, io::sink())?;
+    // A column that the written header does not have cannot be kept
+    assert!(matches!(
+        process_transactions_into(third.as_bytes(), &mut state, &mut options, io::sink()),
+        Err(Error::RejectedRecordColumnMismatch(column)) if column == "timestamp"
+    ));
+    options.flush_rejected_records()?;
+    assert_eq!(
+        fs::read_to_string(rejected.path()).map_err(Error::ReadError)?,
+        "type,client,tx,amount\nwithdrawal,1,1,5.0\nwithdrawal,2,2,3.0\ndispute,2,3,\n"
+    );
+
+    Ok(())
+}
+
+// Tests that a resolved withdrawal dispute credits the withdrawn funds back
+#[test]
+fn test_withdrawal_dispute_and_resolve() -> Result<(), Error> {
+    let result = Scenario::new()
+        .deposit(1, 1, dec!(100.0))
+        .withdrawal(1, 2, dec!(30.0))
+        .dispute(1, 2)
+        .resolve(1, 2)
+        .run()?;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(100.0)),
+            held_funds: MoneyAmount(dec!(0.0)),
+            is_locked: false,
+        }
+    );
+
+    // Stored transactions know their kind
+    let transaction: Transaction = Scenario::new()
+        .withdrawal(1, 2, dec!(30.0))
+        .records
+        .remove(0)
+        .try_into()?;
+    assert_eq!(transaction.kind, TransactionKind::Withdrawal);
+
+    Ok(())
+}
+
+// Tests that amounts at the Decimal scale limit lose their precision, and that it can be observed
+#[test]
+fn test_warn_precision_loss() -> Result<(), Error> {
+    // Adding an amount at the scale limit to a large balance silently loses it
+    let tiny = dec!(0.0000000000000000000000000001);
+    assert_eq!(tiny.scale(), 28);
+    assert_eq!(dec!(1000000) + tiny, dec!(1000000));
+
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 1000000
+	deposit, 1, 2, 0.0000000000000000000000000001
+	deposit, 1, 3, 1.2500"#;
+    let mut options = ProcessingOptions {
+        warn_precision_loss: true,
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    let state = process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Warning: amount 0.0000000000000000000000000001 of transaction 2 has more than 4 decimal \
+         places; its precision is lost\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(1000001.25))
+    );
+
+    Ok(())
+}
+
+// Tests that a client cannot dispute a transaction of another client
+#[test]
+fn test_cross_client_dispute() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 7, dec!(10.0))
+        .deposit(2, 8, dec!(3.0))
+        .dispute(2, 7)
+        .chargeback(2, 7)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 7 does not belong to client 2\n\
+         Error processing transaction: transaction 7 does not belong to client 2\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(10.0)),
+            held_funds: MoneyAmount(dec!(0)),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(2)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(3.0)),
+            held_funds: MoneyAmount(dec!(0)),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+/// A writer whose reader has gone away, like a closed pipe.
+struct ClosedPipe;
+
+impl Write for ClosedPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+// Tests that writing the result to a closed output is not an error
+#[test]
+fn test_write_result_closed_output() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 1.0"#;
+    let (clients, _) = process_transactions(input.as_bytes())?;
+    write_result(&clients, None, &ResultOptions::default(), ClosedPipe)?;
+    write_result_json(&clients, None, &ResultOptions::default(), ClosedPipe)?;
+
+    // Other write errors are still reported
+    let result = write_result(&clients, None, &ResultOptions::default(), &mut [0u8; 4][..]);
+    assert!(matches!(result, Err(Error::FlushError(_))));
+
+    // Transactions grouped by client are still processed once the output is closed
+    #[cfg(feature = "cli")]
+    {
+        let sorted_input = r#"type, client, tx, amount
+	deposit, 1, 1, 1.0
+	deposit, 2, 2, 2.0"#;
+        let mut state = ProcessingState::default();
+        process_sorted_transactions_into(
+            sorted_input.as_bytes(),
+            &mut state,
+            &mut ProcessingOptions::default(),
+            false,
+            &ResultOptions::default(),
+            ClosedPipe,
+            io::sink(),
+        )?;
+        assert_eq!(state.report.stats.deposits, 2);
+
+        let states = process_namespaced_transactions(
+            vec![input.as_bytes()],
+            TransactionStoreKind::Memory,
+            None,
+            &mut ProcessingOptions::default(),
+            io::sink(),
+        )?;
+        for format in [ResultFormat::Csv, ResultFormat::Json] {
+            write_namespaced_result(
+                &states,
+                false,
+                format,
+                &ResultOptions::default(),
+                ClosedPipe,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Tests feeding records one at a time to the engine
+#[test]
+fn test_payment_engine() {
+    let mut engine = PaymentEngine::new();
+    let deposit = TransactionRecord::new(
+        "deposit",
+        ClientId(1),
+        TransactionId(1),
+        Some(MoneyAmount(dec!(2.0))),
+    );
+    assert!(engine.process_record(deposit).is_ok());
+    let withdrawal = TransactionRecord::new(
+        "withdrawal",
+        ClientId(1),
+        TransactionId(2),
+        Some(MoneyAmount(dec!(3.0))),
+    );
+    assert!(matches!(
+        engine.process_record(withdrawal),
+        Err(Error::NotEnoughAvailableFunds(..))
+    ));
+    let dispute = TransactionRecord::new("dispute", ClientId(1), TransactionId(1), None);
+    assert!(engine.process_record(dispute).is_ok());
+    assert_eq!(
+        engine.accounts(),
+        &HashMap::from_iter([(
+            ClientId(1),
+            Client {
+                available_funds: MoneyAmount(dec!(0.0)),
+                held_funds: MoneyAmount(dec!(2.0)),
+                is_locked: false,
+            }
+        )])
+    );
+    assert_eq!(engine.stats().peak_transactions, 1);
+    assert_eq!(engine.stats().peak_clients, 1);
+}
+
+// Tests that interest is credited on the available funds at the end of the run
+#[cfg(feature = "cli")]
+#[test]
+fn test_accrue_interest() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 100.0
+	deposit,    2, 2, 10.0
+	withdrawal, 2, 3, 10.0
+	deposit,    3, 4, 100.0
+	deposit,    3, 5, 20.0
+	dispute,    3, 5"#;
+    let mut state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    state.accrue_interest(500)?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(105.0))
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(2)).unwrap().available_funds,
+        MoneyAmount(dec!(0.0))
+    );
+    // Held funds do not earn interest
+    assert_eq!(
+        state.clients.get(&ClientId(3)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(105.0)),
+            held_funds: MoneyAmount(dec!(20.0)),
+            is_locked: false,
+        }
+    );
+    state.audit_funds()?;
+
+    Ok(())
+}
+
+// Tests that interest overflowing a balance is an error instead of a panic
+#[cfg(feature = "cli")]
+#[test]
+fn test_accrue_interest_overflow() {
+    let mut state = ProcessingState::default();
+    state.clients.insert(
+        ClientId(1),
+        Client {
+            available_funds: MoneyAmount(Decimal::MAX),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        state.accrue_interest(10_000),
+        Err(Error::BalanceOverflow(ClientId(1)))
+    ));
+}
+
+// Tests that the result is sorted by client ID
+#[test]
+fn test_sorted_result() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 10, 1, 1.0
+	deposit, 2,  2, 2.0
+	deposit, 1,  3, 3.0"#;
+    let (clients, _) = process_transactions(input.as_bytes())?;
+    let mut output = Vec::new();
+    write_result(&clients, None, &ResultOptions::default(), &mut output)?;
+    assert_eq!(
+        output,
+        b"client,available,held,total,locked\n\
+          1,3,0,3,false\n\
+          2,2,0,2,false\n\
+          10,1,0,1,false\n"
+    );
+
+    Ok(())
+}
+
+// Tests that disputes beyond the maximum per client are rejected
+#[test]
+fn test_max_disputes_per_client() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(1.0))
+        .deposit(1, 2, dec!(2.0))
+        .deposit(1, 3, dec!(4.0))
+        .deposit(2, 4, dec!(8.0))
+        .dispute(1, 1)
+        .dispute(1, 9) // Unknown transactions are not counted
+        .resolve(1, 1)
+        .dispute(1, 2)
+        .dispute(1, 3)
+        .dispute(2, 4)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut options = ProcessingOptions {
+        max_disputes_per_client: Some(2),
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    process_records(records, &mut state, &mut options, &mut log)?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: unknown transaction ID: 9\n\
+         Error processing transaction: client 1 has opened too many disputes\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().held_funds,
+        MoneyAmount(dec!(2.0))
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(2)).unwrap().held_funds,
+        MoneyAmount(dec!(8.0))
+    );
+
+    Ok(())
+}
+
+// Tests that several inputs are processed as a single one
+#[cfg(feature = "cli")]
+#[test]
+fn test_chained_transactions() -> Result<(), Error> {
+    let first_input = r#"type, client, tx, amount
+	deposit, 1, 1, 10.0
+	deposit, 2, 2, 5.0"#;
+    let second_input = r#"type, client, tx, amount
+	dispute,    1, 1
+	withdrawal, 2, 3, 1.0"#;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_chained_transactions_into(
+        vec![first_input.as_bytes(), second_input.as_bytes()],
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert!(log.is_empty());
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(0.0)),
+            held_funds: MoneyAmount(dec!(10.0)),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(2)).unwrap().available_funds,
+        MoneyAmount(dec!(4.0))
+    );
+
+    Ok(())
+}
+
+// Tests that negative IDs are reported with their field and value
+#[test]
+fn test_negative_ids() {
+    let input = r#"type, client, tx, amount
+	deposit, -1, 1, 1.0"#;
+    let result = process_transactions(input.as_bytes());
+    assert!(matches!(result, Err(Error::NegativeId(id)) if id == "client -1"));
+
+    let input = r#"type, client, tx, amount
+	deposit, 1, -12, 1.0"#;
+    let result = process_transactions(input.as_bytes());
+    assert!(matches!(result, Err(Error::NegativeId(id)) if id == "tx -12"));
+
+    // Other invalid IDs are still generic parsing errors
+    let input = r#"type, client, tx, amount
+	deposit, 1, -x, 1.0"#;
+    let result = process_transactions(input.as_bytes());
+    assert!(matches!(result, Err(Error::ParsingError(_))));
+}
+
+// Tests that the transaction file is optional, stdin being read instead
+#[cfg(feature = "cli")]
+#[test]
+fn test_optional_transaction_file() {
+    let args = Args::try_parse_from(["payments"]).unwrap();
+    assert!(args.transactions_filepaths.is_empty());
+
+    let args = Args::try_parse_from(["payments", "transactions.csv"]).unwrap();
+    assert_eq!(
+        args.transactions_filepaths,
+        [PathBuf::from("transactions.csv")]
+    );
+
+    // Following requires a file
+    assert!(Args::try_parse_from(["payments", "--follow"]).is_err());
+}
+
+// Tests that namespaced runs accept the output and summary options, but not a single opening state
+#[cfg(feature = "cli")]
+#[test]
+fn test_namespace_by_file_args() {
+    let args = Args::try_parse_from([
+        "payments",
+        "--namespace-by-file",
+        "--format",
+        "json",
+        "--quiet-errors",
+        "--explain-errors",
+        "--report-peak-sizes",
+        "--tally-locked-rejections",
+        "--delta-output",
+        "delta.csv",
+        "--json-dir",
+        "clients",
+        "a.csv",
+        "b.csv",
+    ]);
+    assert!(args.is_ok());
+
+    let args = Args::try_parse_from([
+        "payments",
+        "--namespace-by-file",
+        "--resume-state",
+        "state.json",
+        "a.csv",
+    ]);
+    assert!(args.is_err());
+}
+
+// Tests that a JSON file is written for every client
+#[cfg(feature = "cli")]
+#[test]
+fn test_json_dir() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 1, 1, 1.5
+	deposit, 2, 2, 2.0
+	dispute, 2, 2"#;
+    let (clients, _) = process_transactions(input.as_bytes())?;
+    let directory = tempfile::tempdir().map_err(Error::ReadError)?;
+    let json_dir = directory.path().join("clients");
+    write_json_dir(&clients, &json_dir, &ResultOptions::default())?;
+
+    let mut file_names: Vec<_> = fs::read_dir(&json_dir)
+        .map_err(Error::ReadError)?
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    file_names.sort();
+    assert_eq!(file_names, ["1.json", "2.json"]);
+    assert_eq!(
+        fs::read_to_string(json_dir.join("1.json")).map_err(Error::ReadError)?,
+        r#"{
+  "client": 1,
+  "available": "1.5",
+  "held": "0",
+  "total": "1.5",
+  "locked": false
+}"#
+    );
+    assert_eq!(
+        fs::read_to_string(json_dir.join("2.json")).map_err(Error::ReadError)?,
+        r#"{
+  "client": 2,
+  "available": "0",
+  "held": "2",
+  "total": "2",
+  "locked": false
+}"#
+    );
+
+    Ok(())
+}
+
+// Tests that the JSON result has the same fields and rounding as the CSV result
+#[test]
+fn test_write_result_json() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit, 2, 1, 2.00005
+	deposit, 1, 2, 1.5
+	dispute, 1, 2"#;
+    let (clients, _) = process_transactions(input.as_bytes())?;
+    let mut output = Vec::new();
+    write_result_json(&clients, None, &ResultOptions::default(), &mut output)?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        r#"[
+  {
+    "client": 1,
+    "available": "0.0",
+    "held": "1.5",
+    "total": "1.5",
+    "locked": false
+  },
+  {
+    "client": 2,
+    "available": "2.0000",
+    "held": "0",
+    "total": "2.0000",
+    "locked": false
+  }
+]
+"#
+    );
+
+    Ok(())
+}
+
+// Tests that transaction errors are aggregated by kind and ranked by frequency
+#[test]
+fn test_explain_errors() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 1.0
+	dispute,    1, 7
+	withdrawal, 1, 2, 5.0
+	dispute,    1, 8
+	withdrawal, 1, 3, 5.0
+	dispute,    1, 9"#;
+    let state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    let stats = &state.report.stats;
+    assert_eq!(
+        stats.ranked_rejections(),
+        [("UnknownTransactionId", 3), ("NotEnoughAvailableFunds", 2)]
+    );
+    assert_eq!(
+        stats.sample_transaction_ids,
+        HashMap::from_iter([
+            ("UnknownTransactionId".to_string(), TransactionId(7)),
+            ("NotEnoughAvailableFunds".to_string(), TransactionId(2)),
+        ])
+    );
+
+    Ok(())
+}
+
+// Tests that a transaction reusing the ID of a stored one is rejected
+#[test]
+fn test_duplicate_transaction_id() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(5.0))
+        .deposit(1, 1, dec!(100.0))
+        .withdrawal(2, 1, dec!(1.0))
+        .dispute(1, 1)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction ID 1 is already used\n\
+         Error processing transaction: transaction ID 1 is already used\n"
+    );
+    // The dispute targets the original deposit
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(0.0)),
+            held_funds: MoneyAmount(dec!(5.0)),
+            is_locked: false,
+        }
+    );
+    assert!(!state.clients.contains_key(&ClientId(2)));
+
+    Ok(())
+}
+
+// Tests that the held funds are the disputed funds plus the pending held deposits
+#[cfg(feature = "cli")]
+#[test]
+fn test_disputed_funds_with_hold() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(2.0))
+        .record("hold", 1, 2, Some(dec!(5.0)))
+        .dispute(1, 1)
+        .records;
+    let mut state = ProcessingState::default();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().held_funds,
+        MoneyAmount(dec!(7.0))
+    );
+    assert_eq!(
+        state.disputed_funds()?.get(&ClientId(1)),
+        Some(&MoneyAmount(dec!(2.0)))
+    );
+
+    Ok(())
+}
+
+// Tests that a voided held deposit is removed and cannot be disputed
+#[cfg(feature = "cli")]
+#[test]
+fn test_void() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(2.0))
+        .record("hold", 1, 2, Some(dec!(5.0)))
+        .record("void", 1, 2, None)
+        .dispute(1, 2)
+        .record("settle", 1, 2, None)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 2 has been voided\n\
+         Error processing transaction: transaction 2 is not pending\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(dec!(2.0)),
+            held_funds: MoneyAmount(dec!(0.0)),
+            is_locked: false,
+        }
+    );
+    state.audit_funds()?;
+
+    Ok(())
+}
+
+// Tests that the result does not depend on the hasher of the internal maps, as this test passes
+// with and without the `fxhash` feature
+#[cfg(feature = "cli")]
+#[test]
+fn test_hasher_independent_result() -> Result<(), Error> {
+    let mut transactions = Vec::new();
+    generate_transactions(5, 200, 42, &mut transactions)?;
+    let (clients, _) = process_transactions(transactions.as_slice())?;
+    let mut result = Vec::new();
+    write_result(&clients, None, &ResultOptions::default(), &mut result)?;
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "client,available,held,total,locked\n\
+         1,1279.9853,0.0000,1279.9853,false\n\
+         2,56.9002,1049.2915,1106.1917,false\n\
+         3,1441.0845,0.0000,1441.0845,false\n\
+         4,701.8841,654.1302,1356.0143,false\n\
+         5,500.8162,363.9759,864.7921,false\n"
+    );
+
+    Ok(())
+}
+
+// Tests that a deposit making the balance overflow is rejected instead of panicking
+#[test]
+fn test_balance_overflow() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, Decimal::MAX - dec!(1))
+        .deposit(1, 2, Decimal::MAX - dec!(1))
+        .records;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: client 1: balance overflow\n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: MoneyAmount(Decimal::MAX - dec!(1)),
+            held_funds: MoneyAmount(dec!(0)),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that transactions dated after the current time are rejected
+#[test]
+fn test_reject_future_timestamps() -> Result<(), Error> {
+    // 1704067200 is 2024-01-01T00:00:00Z
+    let input = r#"type, client, tx, amount, timestamp
+	deposit,    1, 1, 10.0, 1704067100
+	deposit,    1, 2, 5.0,  1704067200
+	deposit,    1, 3, 1.0,  1704067201
+	deposit,    1, 4, 2.0"#;
+    let mut options = ProcessingOptions {
+        reject_future_timestamps: Some(Box::new(FixedClock(Timestamp(1704067200)))),
+        ..Default::default()
+    };
+    let mut log = Vec::new();
+    let result =
+        process_transactions_with_options(input.as_bytes(), &mut options, &mut log)?.clients;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 3 is dated in the future\n"
+    );
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(17).into(),
+            held_funds: dec!(0).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that the stats count the applied transactions of each type and the rejections by kind
+#[test]
+fn test_processing_stats() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 10.0
+	deposit,    2, 2, 5.0
+	withdrawal, 1, 3, 20.0
+	withdrawal, 1, 4, 2.0
+	dispute,    1, 1,
+	dispute,    1, 9,
+	resolve,    1, 1,
+	dispute,    2, 2,
+	chargeback, 2, 2,
+	deposit,    2, 5, 1.0
+	withdrawal, 2, 6, 50.0
+	resolve,    1, 7,"#;
+    let (_, stats) = process_transactions(input.as_bytes())?;
+    assert_eq!(
+        stats,
+        ProcessingStats {
+            records: 12,
+            deposits: 2,
+            withdrawals: 1,
+            disputes: 2,
+            resolves: 1,
+            chargebacks: 1,
+            rejections: HashMap::from_iter([
+                ("NotEnoughAvailableFunds".to_string(), 1),
+                ("UnknownTransactionId".to_string(), 2),
+                ("ClientLocked".to_string(), 2),
+            ]),
+            sample_transaction_ids: HashMap::from_iter([
+                ("NotEnoughAvailableFunds".to_string(), TransactionId(3)),
+                ("UnknownTransactionId".to_string(), TransactionId(9)),
+                ("ClientLocked".to_string(), TransactionId(5)),
+            ]),
+            peak_transactions: 3,
+            peak_clients: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        stats.to_string(),
+        "processed 12 records: 5 rejected (2 ClientLocked, 2 UnknownTransactionId, \
+         1 NotEnoughAvailableFunds)"
+    );
+
+    Ok(())
+}
+
+// Tests that both views of the processing result agree
+#[test]
+fn test_processing_result() {
+    let mut engine = PaymentEngine::new();
+    for (client, tx, amount) in [(3, 1, dec!(1.0)), (1, 2, dec!(2.0)), (2, 3, dec!(3.0))] {
+        let deposit = TransactionRecord::new(
+            "deposit",
+            ClientId(client),
+            TransactionId(tx),
+            Some(MoneyAmount(amount)),
+        );
+        assert!(engine.process_record(deposit).is_ok());
+    }
+    let result = engine.into_result();
+    assert_eq!(result.clients().len(), 3);
+    assert_eq!(result.sorted_clients().len(), 3);
+    for (client_id, client) in result.sorted_clients() {
+        assert_eq!(result.get(*client_id), Some(client));
+    }
+    let client_ids: Vec<_> = result
+        .sorted_clients()
+        .iter()
+        .map(|(client_id, _)| *client_id)
+        .collect();
+    assert_eq!(client_ids, [ClientId(1), ClientId(2), ClientId(3)]);
+    assert_eq!(
+        result.get(ClientId(2)).unwrap().available_funds,
+        MoneyAmount(dec!(3.0))
+    );
+}
+
+// Tests that columns are mapped by their header name, whatever their order
+#[test]
+fn test_reordered_columns() -> Result<(), Error> {
+    let input = r#"client, type, amount, tx
+	1, deposit,    10.0, 1
+	1, withdrawal, 2.5,  2
+	2, deposit,    4.0,  3
+	1, dispute,        , 1
+	2, dispute,    1.0,  3"#;
+    let (result, _) = process_transactions(input.as_bytes())?;
+    assert_eq!(
+        result.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(-2.5).into(),
+            held_funds: dec!(10).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        result.get(&ClientId(2)).unwrap(),
+        &Client {
+            available_funds: dec!(3).into(),
+            held_funds: dec!(1).into(),
+            is_locked: false,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that disputes are rejected while too many are open, and accepted again once some close
+#[test]
+fn test_max_open_disputes() -> Result<(), Error> {
+    let records = Scenario::new()
+        .deposit(1, 1, dec!(1.0))
+        .deposit(2, 2, dec!(2.0))
+        .deposit(3, 3, dec!(3.0))
+        .dispute(1, 1)
+        .dispute(2, 2)
+        .dispute(3, 3)
+        .resolve(1, 1)
+        .dispute(3, 3)
+        .chargeback(2, 2)
+        .records;
+    let mut state = ProcessingState::default();
+    let mut log = Vec::new();
+    process_records(
+        records,
+        &mut state,
+        &mut ProcessingOptions {
+            max_open_disputes: Some(2),
+            ..Default::default()
+        },
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: transaction 3: too many disputes are open\n"
+    );
+    assert_eq!(state.open_disputes, 1);
+    assert_eq!(
+        state.clients.get(&ClientId(3)).unwrap().held_funds,
+        MoneyAmount(dec!(3.0))
+    );
+
+    Ok(())
+}
+
+// Tests that disputes can target a transaction imported from a settled state
+#[cfg(feature = "cli")]
+#[test]
+fn test_replay_disputes_from_settled_state() -> Result<(), Error> {
+    let settled_state = r#"{
+        "clients": {
+            "1": { "available_funds": "10", "held_funds": "0", "is_locked": false },
+            "2": { "available_funds": "3", "held_funds": "0", "is_locked": false }
+        },
+        "transactions": {
+            "7": { "client_id": 1, "kind": "Deposit", "amount": "10" },
+            "8": { "client_id": 2, "kind": "Withdrawal", "amount": "2" }
+        }
+    }"#;
+    let input = r#"type, client, tx, amount
+	dispute,    1, 7,
+	dispute,    2, 8,
+	chargeback, 2, 8,"#;
+    let mut state = ProcessingState::from_json(settled_state)?;
+    let mut log = Vec::new();
+    process_transactions_into(
+        input.as_bytes(),
+        &mut state,
+        &mut ProcessingOptions::default(),
+        &mut log,
+    )?;
+    assert!(log.is_empty());
+    // The settled funds are the opening funds of the run
+    assert_eq!(state.report.opening_funds, MoneyAmount(dec!(13)));
+    state.audit_funds()?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(0).into(),
+            held_funds: dec!(10).into(),
+            is_locked: false,
+        }
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(2)).unwrap(),
+        &Client {
+            available_funds: dec!(5).into(),
+            held_funds: dec!(0).into(),
+            is_locked: true,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that disputes already open in a settled state are counted as open disputes
+#[cfg(feature = "cli")]
+#[test]
+fn test_resume_open_disputes() -> Result<(), Error> {
+    let settled_state = r#"{
+        "clients": {
+            "1": { "available_funds": "0", "held_funds": "15", "is_locked": false }
+        },
+        "transactions": {
+            "7": { "client_id": 1, "kind": "Deposit", "amount": "10", "disputed": "Disputed", "disputed_amount": "10" },
+            "8": { "client_id": 1, "kind": "Deposit", "amount": "5", "disputed": "Disputed", "disputed_amount": "5" }
+        }
+    }"#;
+    let input = r#"type, client, tx, amount
+	resolve,    1, 7,
+	chargeback, 1, 8,"#;
+    let mut state = ProcessingState::from_json(settled_state)?;
+    assert_eq!(state.open_disputes, 2);
+    let mut log = Vec::new();
+    process_transactions_into(
+        input.as_bytes(),
+        &mut state,
+        &mut ProcessingOptions {
+            max_open_disputes: Some(1),
+            ..Default::default()
+        },
+        &mut log,
+    )?;
+    assert!(log.is_empty());
+    assert_eq!(state.open_disputes, 0);
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap(),
+        &Client {
+            available_funds: dec!(10).into(),
+            held_funds: dec!(0).into(),
+            is_locked: true,
+        }
+    );
+
+    Ok(())
+}
+
+// Tests that spaces around fields are only trimmed when asked to
+#[cfg(feature = "cli")]
+#[test]
+fn test_trim() -> Result<(), Error> {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\n deposit ,1,2,2.0\n";
+    let read_type = |trim| {
+        let mut reader = transaction_reader_builder(trim).from_reader(input.as_bytes());
+        reader.records().nth(1).unwrap().unwrap()[0].to_string()
+    };
+    assert_eq!(read_type(TrimMode::All), "deposit");
+    assert_eq!(read_type(TrimMode::None), " deposit ");
+
+    let mut log = Vec::new();
+    let state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions {
+            trim: TrimMode::None,
+            ..Default::default()
+        },
+        &mut log,
+    )?;
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error processing transaction: unknown transaction type:  deposit \n"
+    );
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(1.0))
+    );
+    let state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    assert_eq!(
+        state.clients.get(&ClientId(1)).unwrap().available_funds,
+        MoneyAmount(dec!(3.0))
+    );
+
+    Ok(())
+}
+
+// Tests that the number of warnings is encoded in the exit status, offset past the fatal error
+// status and capped
+#[cfg(feature = "cli")]
+#[test]
+fn test_warnings_exit_code() -> Result<(), Error> {
+    let input = r#"type, client, tx, amount
+	deposit,    1, 1, 1.0
+	withdrawal, 1, 2, 5.0
+	dispute,    1, 9,
+	resolve,    1, 1,
+	deposit,    1, 3, 1.0"#;
+    let state = process_transactions_with_options(
+        input.as_bytes(),
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+    assert_eq!(warnings_exit_code(state.report.stats.rejected()), 4);
+    assert_eq!(warnings_exit_code(0), 0);
+    // A single warning is not confused with a fatal error
+    assert_eq!(warnings_exit_code(1), 2);
+    assert_eq!(warnings_exit_code(124), 125);
+    assert_eq!(warnings_exit_code(1000), 125);
+
+    Ok(())
+}
+
+// Tests that rounding the columns separately can make them not add up, unless the total is the sum
+// of the rounded available and held funds
+#[test]
+fn test_consistent_totals() -> Result<(), Error> {
+    let clients = HashMap::from_iter([(
+        ClientId(1),
+        Client {
+            available_funds: dec!(0.00005).into(),
+            held_funds: dec!(0.00005).into(),
+            is_locked: false,
+        },
+    )]);
+    // Midpoints are rounded to the nearest even digit: 0.00005 rounds down but 0.0001 is exact
+    let mut output = Vec::new();
+    write_result(&clients, None, &ResultOptions::default(), &mut output)?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0001,false\n"
+    );
+
+    let options = ResultOptions {
+        consistent_totals: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(&clients, None, &options, &mut output)?;
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+    );
+
+    Ok(())
+}
+
+// Tests that transactions stored with sled give the same balances as when stored in memory
+#[cfg(feature = "sled")]
+#[test]
+fn test_sled_transaction_store() -> Result<(), Error> {
+    let scenario = || {
+        Scenario::new()
+            .deposit(1, 1, dec!(10.0))
+            .deposit(2, 2, dec!(5.0))
+            .withdrawal(1, 3, dec!(4.0))
+            .dispute(1, 1)
+            .dispute(2, 2)
+            .resolve(2, 2)
+            .dispute(1, 3)
+            .chargeback(1, 3)
+            .record("hold", 2, 4, Some(dec!(3.0)))
+            .record("settle", 2, 4, None)
+            .records
+    };
+    let mut memory_state = ProcessingState::default();
+    process_records(
+        scenario(),
+        &mut memory_state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+
+    let directory = tempfile::tempdir().map_err(Error::ReadError)?;
+    let mut sled_state = ProcessingState::default();
+    select_transaction_store(
+        &mut sled_state,
+        TransactionStoreKind::Sled,
+        Some(directory.path()),
+    )?;
+    process_records(
+        scenario(),
+        &mut sled_state,
+        &mut ProcessingOptions::default(),
+        io::sink(),
+    )?;
+
+    assert_eq!(sled_state.clients, memory_state.clients);
+    assert_eq!(sled_state.transactions, memory_state.transactions);
+    assert_eq!(sled_state.transactions.len(), 4);
 
     Ok(())
 }
@@ -0,0 +1,136 @@
+//! Transactions, as read from the input and as stored for disputes.
+
+use crate::{
+    error::Error,
+    types::{ClientId, MoneyAmount, Timestamp, TransactionId},
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// The various states of a disputed transaction.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Display, Serialize)]
+pub(crate) enum DisputedState {
+    /// This transaction is not disputed.
+    #[default]
+    NotDisputed,
+
+    /// This is a disputed transaction.
+    Disputed,
+
+    /// This transaction has been resolved.
+    Resolved,
+
+    /// This transaction has been charged back.
+    ChargedBack,
+}
+
+/// The settlement state of a deposit, which may be held before it settles.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) enum SettlementState {
+    /// The funds of this transaction are settled.
+    #[default]
+    Settled,
+
+    /// This deposit is held until it is settled or voided.
+    Pending,
+
+    /// This deposit has been cancelled before it settled; it cannot be targeted anymore.
+    Voided,
+}
+
+/// The kind of a stored transaction.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) enum TransactionKind {
+    /// A deposit; a credit to the client's asset account.
+    Deposit,
+
+    /// A withdrawal; a debit to the client's asset account.
+    Withdrawal,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// A transaction.
+pub(crate) struct Transaction {
+    /// The client who made this transaction.
+    pub(crate) client_id: ClientId,
+    /// Whether this transaction is a deposit or a withdrawal.
+    pub(crate) kind: TransactionKind,
+    /// The amount of money that has been deposited or withdrawn.
+    pub(crate) amount: MoneyAmount,
+    /// The disputed state of this transaction.
+    #[serde(default)]
+    pub(crate) disputed: DisputedState,
+    /// The part of the amount that is under dispute, or that has been resolved or charged back.
+    #[serde(default)]
+    pub(crate) disputed_amount: MoneyAmount,
+    /// Whether the funds of this transaction are settled.
+    #[serde(default)]
+    pub(crate) settlement: SettlementState,
+}
+
+/// An entry in the transaction input.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "cli", derive(schemars::JsonSchema))]
+pub struct TransactionRecord {
+    /// A string representing the transaction type.
+    #[serde(rename = "type")]
+    pub(crate) type_string: String,
+    /// The client ID that has triggered this transaction.
+    #[serde(rename = "client")]
+    pub(crate) client_id: ClientId,
+    /// The transaction ID can either be the ID of the current transaction, or
+    /// the ID of a target transaction (dispute, resolve, chargeback).
+    #[serde(rename = "tx")]
+    pub(crate) id: TransactionId,
+    /// An amount related to this transaction.
+    pub(crate) amount: Option<MoneyAmount>,
+    /// When this transaction happened, if known.
+    pub(crate) timestamp: Option<Timestamp>,
+}
+
+impl TransactionRecord {
+    /// Creates a transaction record without a timestamp.
+    /// The type is one of `deposit`, `withdrawal`, `dispute`, `resolve`, `chargeback`, `hold`,
+    /// `settle` and `void`.
+    pub fn new(
+        type_string: impl Into<String>,
+        client_id: ClientId,
+        id: TransactionId,
+        amount: Option<MoneyAmount>,
+    ) -> Self {
+        Self {
+            type_string: type_string.into(),
+            client_id,
+            id,
+            amount,
+            timestamp: None,
+        }
+    }
+}
+
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = Error;
+
+    fn try_from(transaction_record: TransactionRecord) -> Result<Self, Self::Error> {
+        let (kind, settlement) = match transaction_record.type_string.as_str() {
+            "deposit" => (TransactionKind::Deposit, SettlementState::Settled),
+            "hold" => (TransactionKind::Deposit, SettlementState::Pending),
+            "withdrawal" => (TransactionKind::Withdrawal, SettlementState::Settled),
+            _ => {
+                return Err(Error::UnknownTransactionType(
+                    transaction_record.type_string,
+                ))
+            }
+        };
+        Ok(Self {
+            client_id: transaction_record.client_id,
+            kind,
+            amount: transaction_record
+                .amount
+                .ok_or(Error::TransactionWithoutAmount)?,
+            disputed: DisputedState::default(),
+            disputed_amount: MoneyAmount::default(),
+            settlement,
+        })
+    }
+}
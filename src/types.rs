@@ -0,0 +1,128 @@
+//! The basic types: identifiers, amounts, timestamps and client accounts.
+
+use derive_more::Display;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "cli")]
+use std::time::SystemTime;
+
+/// A client ID.
+/// Client IDs are ordered numerically, which is the order of the result.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Display, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[cfg_attr(feature = "cli", derive(schemars::JsonSchema), schemars(inline))]
+pub struct ClientId(pub u16);
+
+/// A transaction ID.
+#[derive(Clone, Copy, Debug, Deserialize, Display, Eq, Hash, PartialEq, Serialize)]
+#[cfg_attr(feature = "cli", derive(schemars::JsonSchema), schemars(inline))]
+pub struct TransactionId(pub u32);
+
+/// A point in time, as a number of seconds since the Unix epoch (UTC).
+#[derive(Clone, Copy, Debug, Deserialize, Display, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(schemars::JsonSchema), schemars(inline))]
+pub(crate) struct Timestamp(pub(crate) u64);
+
+impl Timestamp {
+    const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+    /// Number of days since the Unix epoch; changes at every UTC day boundary.
+    pub(crate) fn day(self) -> u64 {
+        self.0 / Self::SECONDS_PER_DAY
+    }
+}
+
+/// A source of the current time, injected so that time-dependent checks can be tested.
+pub(crate) trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// The clock of the system.
+#[cfg(feature = "cli")]
+pub(crate) struct SystemClock;
+
+#[cfg(feature = "cli")]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        // A system clock set before the Unix epoch is considered to be at the epoch
+        Timestamp(
+            SystemTime::UNIX_EPOCH
+                .elapsed()
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// A clock always returning the same time.
+#[cfg(test)]
+pub(crate) struct FixedClock(pub(crate) Timestamp);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.0
+    }
+}
+
+/// An amount of money.
+/// We use a fixed-point decimal number here and not a floating-point one to
+/// prevent any rounding issue and loss of precision as we are in a financial
+/// context.
+/// The performance cost is negligible compared to the impact of a loss in
+/// precision.
+/// Arithmetic operators are not implemented, as amounts come from the input
+/// and must be added and subtracted with overflow checks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Display, PartialEq, PartialOrd, Serialize)]
+#[cfg_attr(feature = "cli", derive(schemars::JsonSchema), schemars(inline))]
+pub struct MoneyAmount(pub Decimal);
+
+/// We implement Deref and DerefMut here for convenience, so that Decimal functions can be called
+/// directly. We could instead provide only access to a selection of functions if wanted.
+impl Deref for MoneyAmount {
+    type Target = Decimal;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for MoneyAmount {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// From trait to convert a Decimal into a MoneyAmount.
+/// We only implement this for tests because it could be too risky to allow converting a Decimal to
+/// a MoneyAmount implicitly by only calling "into()".
+/// In production code we expect users to explicitly do the conversion.
+#[cfg(test)]
+impl From<Decimal> for MoneyAmount {
+    fn from(value: Decimal) -> Self {
+        Self(value)
+    }
+}
+
+pub(crate) const DECIMAL_PRECISION: u32 = 4;
+
+/// Account data for a client.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Client {
+    /// Available funds.
+    pub available_funds: MoneyAmount,
+    /// Held funds.
+    pub held_funds: MoneyAmount,
+    /// Is this account locked?
+    pub is_locked: bool,
+}
+
+impl Client {
+    /// Sum of available and held funds. The sum saturates instead of overflowing.
+    pub fn total_funds(&self) -> MoneyAmount {
+        MoneyAmount(self.available_funds.saturating_add(*self.held_funds))
+    }
+}